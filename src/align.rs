@@ -1,50 +1,95 @@
 use std::fmt;
 use std::fmt::Debug;
-use std::cmp::min;
+use std::cmp::{min, max};
 use matrix::Matrix;
 use seq::*;
 
 
 pub type AlnScore = i32;
 
-const SCOREBITS : usize = 28;
+const STATEBITS : u32 = 3;
 
-/// Cell: bit-pack maximal alignment state and score into 32 bits
+/// CellScore: signed integer types usable as DP cell storage
 ///
-/// layout: 1bit for sign; 3 bits for state (Ins, Del, etc); 28 bits for score
+/// the width bounds the scores a Cell can hold (see Cell); i16 keeps the matrix small for
+/// short alignments, i64 is for very long references or large match scores
+///
+pub trait CellScore: Copy + Clone + Debug + PartialEq + Eq + PartialOrd + Ord + Send + Sync {
+    /// BITS: storage width, including the state bits
+    const BITS: u32;
+    fn from_i64( x: i64 ) -> Self;
+    fn to_i64( self ) -> i64;
+
+    /// max_score: the largest magnitude a packed Cell of this width can hold
+    fn max_score() -> i64 { (1i64 << (Self::BITS - STATEBITS - 1)) - 1 }
+}
+
+macro_rules! cell_score {
+    ($t:ty, $bits:expr) => {
+        impl CellScore for $t {
+            const BITS: u32 = $bits;
+            fn from_i64( x: i64 ) -> $t { x as $t }
+            fn to_i64( self ) -> i64 { self as i64 }
+        }
+    }
+}
+cell_score!(i16, 16);
+cell_score!(i32, 32);
+cell_score!(i64, 64);
+
+/// AlnErr: reasons an alignment can fail outright
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AlnErr {
+    /// a DP score didn't fit in the cell's score width; use a wider CellScore
+    Overflow,
+    /// quals were given, but not one per query base
+    QualLength
+}
+
+/// Cell: bit-pack maximal alignment state and score into a single signed integer
+///
+/// layout: 3 low bits for state (Ins, Del, etc); the remaining high bits hold the score in
+/// two's complement, so a Cell<i32> holds scores of magnitude up to 2^28 - 1, a Cell<i16>
+/// up to 4095, and a Cell<i64> up to 2^60 - 1.  Cells order by score.
 ///
 /// note that a simple Smith-Waterman doesn't require storing the "direction" of the
 /// previous max in the cell, however, we need it to implement separate gap-opening
 /// and gap-extension penalties
 ///
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
-pub struct Cell(pub u32);
+pub struct Cell<S: CellScore = AlnScore>(pub S);
 
-impl Cell {
+impl<S: CellScore> Cell<S> {
     // bit-packing operations
-    pub fn pack( state : &AlnState, score : &AlnScore ) -> Cell {
-        // 0x1fffffff = 29 1's
-        let _state : u32 = if *score < 0 {0x8fffffff} else {0xfffffff} | (match *state {
+
+    /// Cell::pack -- panics if score doesn't fit; see try_pack
+    pub fn pack( state : &AlnState, score : &S ) -> Cell<S> {
+        Cell::try_pack( state, score.to_i64() ).unwrap()
+    }
+
+    pub fn try_pack( state : &AlnState, score : i64 ) -> Result<Cell<S>, AlnErr> {
+        if score > S::max_score() || score < -S::max_score() {
+            return Err(AlnErr::Overflow);
+        }
+        let _state : i64 = match *state {
             AlnState::Nil =>  0,
-            AlnState::Match => 1 << SCOREBITS,
-            AlnState::Mismatch => 2 << SCOREBITS,
-            AlnState::Ins => 3 << SCOREBITS,
-            AlnState::Del => 4 << SCOREBITS });
-
-        // ensure the first 4 bits are either 0111 or 1111, depending on sign
-        let _score : u32 = (*score).abs() as u32 | {
-            if *score < 0 {
-                0xf0000000
-            } else {
-                0x70000000
-            }};
-        Cell(_score & _state)
+            AlnState::Match => 1,
+            AlnState::Mismatch => 2,
+            AlnState::Ins => 3,
+            AlnState::Del => 4 };
+        Ok( Cell( S::from_i64( (score << STATEBITS) | _state ) ) )
     }
 
-    pub fn unpack( c : &Cell ) -> Result<(AlnState, AlnScore), String> {
-        let Cell(packed) = *c;
+    pub fn unpack( c : &Cell<S> ) -> Result<(AlnState, S), String> {
+        let (state, score) = try!( Cell::unpack_wide( c ) );
+        Ok( (state, S::from_i64(score)) )
+    }
+
+    /// Cell::unpack_wide -- as unpack, with the score widened to i64 for checked arithmetic
+    pub fn unpack_wide( c : &Cell<S> ) -> Result<(AlnState, i64), String> {
+        let packed = c.0.to_i64();
         let state = try!(
-            match (packed >> SCOREBITS) & 7 {
+            match packed & ((1 << STATEBITS) - 1) {
                 0 => Ok(AlnState::Nil),
                 1 => Ok(AlnState::Match),
                 2 => Ok(AlnState::Mismatch),
@@ -53,20 +98,19 @@ impl Cell {
                 x => Err(format!("unknown value: {}", x)) }
         );
 
-        // the first bit is a sign bit
-        let score : AlnScore = if (packed & 0x80000000) == 0 {
-            0xfffffff & packed as i32
-        } else {
-            // score was negative
-            -1 * (0xfffffff & packed as i32)
-        };
-        Ok( (state, score) )
+        // arithmetic shift keeps the sign
+        Ok( (state, packed >> STATEBITS) )
+    }
+
+    /// Cell::nil -- the zero-score cell matrices are initialized with
+    pub fn nil() -> Cell<S> {
+        Cell( S::from_i64(0) )
     }
 }
 
-impl fmt::Debug for Cell {
+impl<S: CellScore> fmt::Debug for Cell<S> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let (a, b) = Cell::unpack( &self ).unwrap();
+        let (a, b) = Cell::unpack_wide( &self ).unwrap();
         write!(f, "{:?}({})", a, b)
     }
 }
@@ -74,10 +118,53 @@ impl fmt::Debug for Cell {
 // used to store HMMer-style probabilities; dimensions: BASE-COUNT x REF-LENGTH
 pub type ProbMatr = Matrix<f32>;
 
+/// AlnMode: which ends of the reference and query may be left unaligned at no cost
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlnMode {
+    /// Needleman-Wunsch: both sequences aligned end to end
+    Global,
+    /// Smith-Waterman: the best-scoring pair of substrings; scores are floored at zero
+    Local,
+    /// the whole query, with free end gaps on the reference (query placed within reference)
+    SemiGlobal,
+    /// dovetail: free end gaps on both, so a suffix of one may overlap a prefix of the other
+    Overlap,
+    /// the whole reference, with free end gaps on the query (reference placed within query)
+    QueryLocal
+}
+
+impl AlnMode {
+    pub fn from_name( name: &str ) -> Option<AlnMode> {
+        match name.to_lowercase().as_str() {
+            "global" => Some(AlnMode::Global),
+            "local" => Some(AlnMode::Local),
+            "semiglobal" | "semi-global" => Some(AlnMode::SemiGlobal),
+            "overlap" => Some(AlnMode::Overlap),
+            "querylocal" | "query-local" | "glocal" => Some(AlnMode::QueryLocal),
+            _ => None
+        }
+    }
+
+    /// reference bases before and after the alignment cost nothing
+    pub fn ref_ends_free(&self) -> bool {
+        match *self {
+            AlnMode::Local | AlnMode::SemiGlobal | AlnMode::Overlap => true,
+            _ => false
+        }
+    }
+
+    /// query bases before and after the alignment cost nothing
+    pub fn query_ends_free(&self) -> bool {
+        match *self {
+            AlnMode::Local | AlnMode::Overlap | AlnMode::QueryLocal => true,
+            _ => false
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct AlnParams {
-    pub llocal:     bool,        // global or local on the left?
-    pub rlocal:     bool,        // global or local on the right?
+    pub mode:       AlnMode,     // which ends are free (see AlnMode)
     pub max_indel:  Option<u8>,  // maximum number of indels before short-circuiting
     pub gap_open:   AlnScore,         // penalty for opening a gap
     pub gap_ext:    AlnScore,         // gap extention penalty
@@ -86,11 +173,10 @@ pub struct AlnParams {
 }
 
 impl AlnParams {
-    pub fn new(  _llocal: Option<bool>, _rlocal: Option<bool>, _max_indel: Option<u8>, _gap_open: Option<AlnScore>,
+    pub fn new(  _mode: Option<AlnMode>, _max_indel: Option<u8>, _gap_open: Option<AlnScore>,
                  _gap_ext: Option<AlnScore>, _mismatch: AlnScore, _equal: Option<AlnScore> ) -> AlnParams {
         AlnParams {
-            llocal:     match _llocal { Some(b) => b, None => true },
-            rlocal:     match _rlocal { Some(b) => b, None => true },
+            mode:       match _mode { Some(m) => m, None => AlnMode::SemiGlobal },
             max_indel:  _max_indel,
             gap_open:   match _gap_open { Some(g) => g, None => -2 },
             gap_ext:    match _gap_ext { Some(g) => g, None => -1 },
@@ -98,10 +184,9 @@ impl AlnParams {
             equal:      match _equal { Some(g) => g, None => 1 }, // "match" is a keyword
         }
     }
-    pub fn copy_but_llocal(tgt: &AlnParams, ll: bool ) -> AlnParams {
+    pub fn copy_but_mode(tgt: &AlnParams, mode: AlnMode ) -> AlnParams {
                 AlnParams {
-                    llocal:     ll,
-                    rlocal:     tgt.rlocal,
+                    mode:       mode,
                     max_indel:  tgt.max_indel,
                     gap_open:   tgt.gap_open,
                     gap_ext:    tgt.gap_ext,
@@ -109,6 +194,11 @@ impl AlnParams {
                     equal:      tgt.equal
                 }
     }
+
+    /// gap_cost -- score of a gap of length k
+    pub fn gap_cost(&self, k: i64) -> i64 {
+        if k <= 0 { 0 } else { self.gap_open as i64 + (k - 1) * self.gap_ext as i64 }
+    }
 }
 
 #[derive(Debug,Clone)]
@@ -138,59 +228,217 @@ impl Eq for AlnState {}
 /// params can specify a max_indel, in which case this can return None if a
 ///   solution can't be found with fewer indels
 ///
-/// returns Option(m, i, j), where m is the alignment matrix, and i,j is the
-///   the location of the cell with the highest value
+/// returns Ok((i, j)), the location in m of the best cell the alignment may end in under
+///   params.mode (see best_end), or Err(AlnErr::Overflow) if a score doesn't fit in the
+///   matrix's cell width
+///
+pub fn align_matrix<S: CellScore>( reference: &Sequence, query: &Sequence, params: &AlnParams, _ref_offset: Option<i32>,
+                 m: &mut Matrix<Cell<S>> ) -> Result<(/*x*/ i32, /*y*/i32), AlnErr> {
+    align_matrix_qual( reference, query, None, params, _ref_offset, m )
+}
+
+/// phred_scale -- scale a match/mismatch score by the probability that a basecall of
+///   Phred quality `qual` is correct.  Scores are integers, so the result is rounded; larger
+///   `equal`/`mismatch` magnitudes give finer resolution.
+pub fn phred_scale( score: AlnScore, qual: u8 ) -> AlnScore {
+    let p_correct = 1. - 10f64.powf( -(qual as f64) / 10. );
+    (score as f64 * p_correct).round() as AlnScore
+}
+
+// (match, mismatch) score for each query position, or Err(AlnErr::QualLength) if quals
+//   doesn't hold one per query base
+fn qual_scores( query: &Sequence, quals: Option<&[u8]>, params: &AlnParams ) -> Result<Vec<(AlnScore, AlnScore)>, AlnErr> {
+    match quals {
+        Some(q) if q.len() != query.len() => Err(AlnErr::QualLength),
+        Some(q) => Ok( q.iter().map( |&x| (phred_scale( params.equal, x ), phred_scale( params.mismatch, x )) ).collect() ),
+        None => Ok( vec![ (params.equal, params.mismatch); query.len() ] )
+    }
+}
+
+/// Dynamic-programming alignment with optional base qualities
 ///
-pub fn align_matrix( reference: &Sequence, query: &Sequence, params: &AlnParams, _ref_offset: Option<i32>,
-                 m: &mut Matrix<Cell> ) -> Option<(/*x*/ i32, /*y*/i32)> {
+/// like align_matrix, but if `quals` (Phred scores, one per query base, without the ASCII
+///   offset) is given, match and mismatch scores at each query position are scaled by the
+///   probability that its basecall is correct, so low-quality bases count for little either way.
+///   quals of the wrong length give Err(AlnErr::QualLength).
+///
+pub fn align_matrix_qual<S: CellScore>( reference: &Sequence, query: &Sequence, quals: Option<&[u8]>, params: &AlnParams,
+                          _ref_offset: Option<i32>, m: &mut Matrix<Cell<S>> ) -> Result<(/*x*/ i32, /*y*/i32), AlnErr> {
+    fill_matrix( reference, query, quals, params, _ref_offset, None, m )
+}
+
+// the DP proper; cells set in `mask` are forced to (Nil, 0), barring alignments through them
+fn fill_matrix<S: CellScore>( reference: &Sequence, query: &Sequence, quals: Option<&[u8]>, params: &AlnParams,
+                              _ref_offset: Option<i32>, mask: Option<&Matrix<bool>>, m: &mut Matrix<Cell<S>> )
+                              -> Result<(/*x*/ i32, /*y*/i32), AlnErr> {
     let ref_len : i32 = reference.len() as i32;
     let ref_offset = match _ref_offset { Some(x) => x, None => 0i32 };
     let query_len : i32 = query.len() as i32;
 
-    let mut best_val : AlnScore = AlnScore::min_value();
-    let mut best_loc : (i32, i32) = (0,0);
 
-    // initialize edges
-    if !params.llocal {
-        for i in 0 .. ref_len + 1 { m[ (i as i32, 0) ] = Cell::pack( &AlnState::Nil, &(-i as AlnScore)); }
+    // (match, mismatch) score for each query position
+    let scores = try!( qual_scores( query, quals, params ) );
+
+    // initialize edges: free ends start at zero, otherwise with the cost of a leading gap.
+    //   Past the first column, row 0 extends the deletion in the column before; that column
+    //   belongs to the previous node, so is left as it is (after a repeat, it holds the best
+    //   copy count's deletion rather than all of them)
+    let nil = Cell::nil();
+    for i in (if ref_offset > 0 { 1 } else { 0 }) .. ref_len + 1 {
+        let k = ref_offset + i;
+        m[ (k, 0) ] = if params.mode.ref_ends_free() || k == 0 { nil.clone() } else {
+            let (state, prev) = Cell::unpack_wide( &m[ (k - 1, 0) ] ).unwrap();
+            try!( Cell::try_pack( &AlnState::Del, prev + if state == AlnState::Del { params.gap_ext } else { params.gap_open } as i64 ) )
+        };
+    }
+    for j in 0 .. query_len + 1 {
+        m[ (0, j) ] = if params.mode.query_ends_free() || j == 0 { nil.clone() } else {
+            try!( Cell::try_pack( &AlnState::Ins, params.gap_cost( j as i64 ) ) )
+        };
     }
-    for j in 0i16 .. (query_len + 1) as i16 { m[ (0, j as i32) ] = Cell::pack( &AlnState::Nil, &(-j as AlnScore)); }
 
     for i in 1 .. ref_len + 1 {
         for j in 1 .. query_len + 1 {
 
             let m_i = ref_offset + i;
 
-            let (dstate, del) = Cell::unpack( &m[ (m_i-1, j) ] ).unwrap();
-            let del_score = del + match (params.rlocal && i == ref_len, dstate) {
-                (true, _) => 0,
-                (_, AlnState::Del) => params.gap_ext,
-                _ => params.gap_open
-            };
+            if let Some(mk) = mask {
+                if mk[ (m_i, j) ] {
+                    m[ (m_i, j) ] = nil.clone();
+                    continue;
+                }
+            }
+
+            let (dstate, del) = Cell::unpack_wide( &m[ (m_i-1, j) ] ).unwrap();
+            let del_score = del + if dstate == AlnState::Del { params.gap_ext } else { params.gap_open } as i64;
 
-            let (istate, ins) = Cell::unpack( &m[ (m_i, j-1) ] ).unwrap();
-            let ins_score = ins + if istate == AlnState::Ins { params.gap_ext } else { params.gap_open };
+            let (istate, ins) = Cell::unpack_wide( &m[ (m_i, j-1) ] ).unwrap();
+            let ins_score = ins + if istate == AlnState::Ins { params.gap_ext } else { params.gap_open } as i64;
 
-            let (_, diag) = Cell::unpack( &m[ (m_i-1, j-1) ] ).unwrap();
-            let diag_score = diag + if reference[i-1] == query[j-1] { params.equal } else { params.mismatch };
+            let (_, diag) = Cell::unpack_wide( &m[ (m_i-1, j-1) ] ).unwrap();
+            let (eq_sc, mm_sc) = scores[ (j-1) as usize ];
+            let diag_score = diag + if bases_match( reference[i-1], query[j-1] ) { eq_sc } else { mm_sc } as i64;
 
             let (a,b) = {
                 if diag_score >= del_score && diag_score >= ins_score {
-                    (if reference[i-1] == query[j-1] {AlnState::Match} else {AlnState::Mismatch}, diag_score)
+                    (if bases_match( reference[i-1], query[j-1] ) {AlnState::Match} else {AlnState::Mismatch}, diag_score)
                 } else if del_score > diag_score && del_score >= ins_score {
                     (AlnState::Del, del_score)
                 } else {
                     (AlnState::Ins, ins_score)
                 }};
 
-            if b > best_val {
-                best_val = b;
-                best_loc = (m_i, j);
-            }
-            m[ (m_i, j) ] = Cell::pack( &a, &b );
+            // Smith-Waterman: never worse than starting afresh
+            let (a, b) = if params.mode == AlnMode::Local && b < 0 { (AlnState::Nil, 0) } else { (a, b) };
+
+            m[ (m_i, j) ] = try!( Cell::try_pack( &a, b ) );
         }
     };
-    Some((best_loc.0, best_loc.1))
+    match best_end( m, (ref_offset, ref_offset + ref_len), query_len, true, params.mode ) {
+        Some((_, loc)) => Ok(loc),
+        None => Ok((ref_offset + ref_len, query_len))
+    }
+}
+
+/// best_end -- the highest-scoring cell an alignment may end in, among reference columns
+///   cols.0 ..= cols.1 of m
+///
+/// in Local mode any cell qualifies, and with free reference ends any cell in the query's
+///   last row.  If `ref_end`, column cols.1 is the end of the reference, so its last cell
+///   (or, with free query ends, any of its cells) qualifies too.
+///
+pub fn best_end<S: CellScore>( m: &Matrix<Cell<S>>, cols: (i32, i32), query_len: i32, ref_end: bool, mode: AlnMode )
+                               -> Option<(/*score*/ i64, /*loc*/ (i32, i32))> {
+    let mut best : Option<(i64, (i32, i32))> = None;
+    {
+        let mut consider = |i: i32, j: i32| {
+            let score = Cell::unpack_wide( &m[ (i, j) ] ).unwrap().1;
+            let better = match best { Some((b, _)) => score > b, None => true };
+            if better {
+                best = Some((score, (i, j)));
+            }
+        };
+
+        for i in cols.0 .. cols.1 + 1 {
+            if mode == AlnMode::Local {
+                for j in 0 .. query_len + 1 { consider(i, j); }
+            } else if mode.ref_ends_free() {
+                consider(i, query_len);
+            }
+        }
+        if ref_end && cols.1 >= cols.0 {
+            if mode.query_ends_free() {
+                for j in 0 .. query_len + 1 { consider(cols.1, j); }
+            } else {
+                consider(cols.1, query_len);
+            }
+        }
+    }
+    best
+}
+
+/// pad_unaligned -- append reference bases against gaps, then query bases against gaps, as
+///   at the unaligned ends of an alignment
+pub fn pad_unaligned( padded_ref: &mut Vec<Mmer>, padded_query: &mut Vec<Mmer>, r: &[Mmer], q: &[Mmer] ) {
+    padded_ref.extend( r.iter().cloned().chain( q.iter().map( |_| HYPHEN ) ) );
+    padded_query.extend( r.iter().map( |_| HYPHEN ).chain( q.iter().cloned() ) );
+}
+
+/// traceback -- padded (reference, query) for the alignment ending at `end` in m, found by
+///   following the state stored in each cell back to a Nil cell.  Bases left unaligned at
+///   either end are padded against gaps, so the padded sequences are full length.
+///
+pub fn traceback<S: CellScore>( end: (i32, i32), reference: &Sequence, query: &Sequence, m: &Matrix<Cell<S>> )
+                                -> (Sequence, Sequence) {
+    let (padded_ref, padded_query, _) = trace( end, reference, query, m );
+    (padded_ref, padded_query)
+}
+
+// traceback, also returning the cells visited
+fn trace<S: CellScore>( end: (i32, i32), reference: &Sequence, query: &Sequence, m: &Matrix<Cell<S>> )
+                        -> (Sequence, Sequence, Vec<(i32, i32)>) {
+    let (mut i, mut j) = end;
+    let mut core_r : Vec<Mmer> = Vec::new();
+    let mut core_q : Vec<Mmer> = Vec::new();
+    let mut cells : Vec<(i32, i32)> = Vec::new();
+
+    while i > 0 || j > 0 {
+        let state = Cell::unpack_wide( &m[ (i, j) ] ).unwrap().0;
+        cells.push( (i, j) );
+        match state {
+            AlnState::Match | AlnState::Mismatch if i > 0 && j > 0 => {
+                core_r.push( reference[i-1] );
+                core_q.push( query[j-1] );
+                i -= 1;
+                j -= 1;
+            },
+            AlnState::Del if i > 0 => {
+                core_r.push( reference[i-1] );
+                core_q.push( HYPHEN );
+                i -= 1;
+            },
+            AlnState::Ins if j > 0 => {
+                core_r.push( HYPHEN );
+                core_q.push( query[j-1] );
+                j -= 1;
+            },
+            _ => {
+                cells.pop();
+                break
+            }
+        }
+    }
+    core_r.reverse();
+    core_q.reverse();
+
+    let mut padded_ref : Vec<Mmer> = Vec::with_capacity( reference.len() + query.len() );
+    let mut padded_query : Vec<Mmer> = Vec::with_capacity( reference.len() + query.len() );
+    pad_unaligned( &mut padded_ref, &mut padded_query, &reference.0[.. i as usize], &query.0[.. j as usize] );
+    padded_ref.extend( core_r );
+    padded_query.extend( core_q );
+    pad_unaligned( &mut padded_ref, &mut padded_query, &reference.0[end.0 as usize ..], &query.0[end.1 as usize ..] );
+
+    (Sequence(padded_ref), Sequence(padded_query), cells)
 }
 
 pub fn align_hmm( reference: ProbMatr, query: Sequence, params: AlnParams ) -> Option<()> {
@@ -198,8 +446,8 @@ pub fn align_hmm( reference: ProbMatr, query: Sequence, params: AlnParams ) -> O
     None
 }
 
-pub fn aln_from_coord( st_i : &i32, st_j : &i32, _inc : &i32, reference : &Sequence,
-                   query : &Sequence, alignment : &Matrix<Cell> )
+pub fn aln_from_coord<S: CellScore>( st_i : &i32, st_j : &i32, _inc : &i32, reference : &Sequence,
+                   query : &Sequence, alignment : &Matrix<Cell<S>> )
                    -> (Sequence, Sequence) {
 
     let ref_len : i32 = reference.len() as i32;
@@ -255,26 +503,291 @@ pub fn aln_from_coord( st_i : &i32, st_j : &i32, _inc : &i32, reference : &Seque
 
 pub fn align( reference: &Sequence, query: &Sequence, params: &AlnParams )
               -> Option<(Sequence, Sequence)> {
-    let mut m = Matrix::<Cell>::new( Cell(0), reference.len() + 2, query.len() + 2 );
+    align_qual( reference, query, None, params )
+}
 
-    match align_matrix( reference, query, &params, None, &mut m ) {
-        Some( (st_i, st_j) ) => {
+/// align_qual -- as align, scaling scores by query base quality (see align_matrix_qual)
+pub fn align_qual( reference: &Sequence, query: &Sequence, quals: Option<&[u8]>, params: &AlnParams )
+                   -> Option<(Sequence, Sequence)> {
+    align_cells::<AlnScore>( reference, query, quals, params ).ok()
+}
 
-            let (fwd_r, fwd_q) = aln_from_coord( &st_i, &st_j, &1, &reference, &query, &m );
-            let (rev_r1, rev_q1) = aln_from_coord( &st_i, &st_j, &(-1), &reference, &query, &m );
+/// align_cells -- as align_qual, with the DP score width chosen by S, and an error rather
+///   than None if a score overflows it
+pub fn align_cells<S: CellScore>( reference: &Sequence, query: &Sequence, quals: Option<&[u8]>, params: &AlnParams )
+                                  -> Result<(Sequence, Sequence), AlnErr> {
+    let mut m = Matrix::<Cell<S>>::new( Cell::nil(), reference.len() + 2, query.len() + 2 );
 
-            let mut rev_r2 = rev_r1.reverse().0;
-            if st_i <= reference.len() as i32 {
-                rev_r2.push( reference[st_i-1] );
-            }
+    let end = try!( align_matrix_qual( reference, query, quals, &params, None, &mut m ) );
+    Ok( traceback( end, reference, query, &m ) )
+}
+
+/// align_top_k -- up to k best local alignments of query against reference, best first,
+///   as (score, padded_ref, padded_query)
+///
+/// uses Waterman-Eggert declumping: once an alignment is reported, the cells on its
+///   traceback are barred and the DP is recomputed, so no two alignments share an aligned
+///   pair of bases.  params.mode is ignored; alignments are always local.
+///
+pub fn align_top_k<S: CellScore>( reference: &Sequence, query: &Sequence, quals: Option<&[u8]>, params: &AlnParams,
+                                  k: usize ) -> Result<Vec<(i64, Sequence, Sequence)>, AlnErr> {
+    let local = AlnParams::copy_but_mode( params, AlnMode::Local );
+    let mut mask = Matrix::<bool>::new( false, reference.len() + 2, query.len() + 2 );
+    let mut hits = Vec::new();
+
+    while hits.len() < k {
+        let mut m = Matrix::<Cell<S>>::new( Cell::nil(), reference.len() + 2, query.len() + 2 );
+        let end = try!( fill_matrix( reference, query, quals, &local, None, Some(&mask), &mut m ) );
+        let score = Cell::unpack_wide( &m[end] ).unwrap().1;
+        if score <= 0 {
+            break;
+        }
+        let (padded_ref, padded_query, cells) = trace( end, reference, query, &m );
+        for c in cells {
+            mask[c] = true;
+        }
+        hits.push( (score, padded_ref, padded_query) );
+    }
+    Ok(hits)
+}
+
+/// align_banded -- as align_cells, filling only the cells whose diagonal (reference
+///   position minus query position) lies within `diags` (inclusive), so time and space go
+///   with the band's width times the reference length rather than the query's length.  The
+///   band is widened as needed to hold the start and end cells the mode requires.  The
+///   result is the best alignment within the band, which is the best overall if the band
+///   holds it.
+///
+pub fn align_banded<S: CellScore>( reference: &Sequence, query: &Sequence, quals: Option<&[u8]>, params: &AlnParams,
+                                   diags: (i64, i64) ) -> Result<(Sequence, Sequence), AlnErr> {
+    let ref_len = reference.len() as i64;
+    let query_len = query.len() as i64;
+    let (mut d_lo, mut d_hi) = diags;
+    if !params.mode.query_ends_free() {
+        d_lo = min( d_lo, 0 );
+        d_hi = max( d_hi, 0 );
+    }
+    if !params.mode.ref_ends_free() {
+        d_lo = min( d_lo, ref_len - query_len );
+        d_hi = max( d_hi, ref_len - query_len );
+    }
+    // clip the band to the diagonals that exist
+    d_lo = max( d_lo, -query_len );
+    d_hi = min( d_hi, ref_len );
+    if d_lo > d_hi {
+        return align_cells::<S>( reference, query, quals, params );
+    }
+
+    let scores = try!( qual_scores( query, quals, params ) );
+
+    // column i holds query positions i - d_hi ..= i - d_lo, at rows 0 ..= d_hi - d_lo
+    let height = (d_hi - d_lo + 1) as usize;
+    let mut m = Matrix::<Cell<S>>::new( Cell::nil(), reference.len() + 1, height );
+    let in_band = |i: i64, j: i64| j >= 0 && j <= query_len && i - j >= d_lo && i - j <= d_hi;
+    let at = |i: i64, j: i64| (i as i32, (j - i + d_hi) as i32);
+
+    for i in 0 .. ref_len + 1 {
+        for j in max( 0, i - d_hi ) .. min( query_len, i - d_lo ) + 1 {
+            let cell = if i == 0 && j == 0 {
+                Cell::nil()
+            } else if i == 0 {
+                if params.mode.query_ends_free() { Cell::nil() } else { try!( Cell::try_pack( &AlnState::Ins, params.gap_cost( j ) ) ) }
+            } else if j == 0 {
+                if params.mode.ref_ends_free() { Cell::nil() } else { try!( Cell::try_pack( &AlnState::Del, params.gap_cost( i ) ) ) }
+            } else {
+                let step = |i: i64, j: i64, same: AlnState| -> Option<i64> {
+                    if !in_band( i, j ) {
+                        return None;
+                    }
+                    let (state, score) = Cell::unpack_wide( &m[ at( i, j ) ] ).unwrap();
+                    Some( score + if state == same { params.gap_ext } else { params.gap_open } as i64 )
+                };
+                let del = step( i - 1, j, AlnState::Del );
+                let ins = step( i, j - 1, AlnState::Ins );
+                let (eq_sc, mm_sc) = scores[ (j - 1) as usize ];
+                let is_eq = bases_match( reference.0[ (i - 1) as usize ], query.0[ (j - 1) as usize ] );
+                let diag = Cell::unpack_wide( &m[ at( i - 1, j - 1 ) ] ).unwrap().1 + if is_eq { eq_sc } else { mm_sc } as i64;
+
+                // ties go as in fill_matrix: match, then deletion, then insertion
+                let (mut a, mut b) = (if is_eq { AlnState::Match } else { AlnState::Mismatch }, diag);
+                if let Some(d) = del {
+                    if d > b && ins.map( |x| d >= x ).unwrap_or(true) {
+                        a = AlnState::Del;
+                        b = d;
+                    }
+                }
+                if let Some(x) = ins {
+                    if x > b {
+                        a = AlnState::Ins;
+                        b = x;
+                    }
+                }
+                if params.mode == AlnMode::Local && b < 0 {
+                    a = AlnState::Nil;
+                    b = 0;
+                }
+                try!( Cell::try_pack( &a, b ) )
+            };
+            m[ at( i, j ) ] = cell;
+        }
+    }
 
-            let mut rev_q2 = rev_q1.reverse().0;
-            if st_j <= query.len() as i32 {
-                rev_q2.push( query[st_j-1] );
+    // the best end cell, as best_end
+    let mut best : Option<(i64, (i64, i64))> = None;
+    {
+        let mut consider = |i: i64, j: i64| {
+            if in_band( i, j ) {
+                let score = Cell::unpack_wide( &m[ at( i, j ) ] ).unwrap().1;
+                if best.map( |b| score > b.0 ).unwrap_or(true) {
+                    best = Some((score, (i, j)));
+                }
+            }
+        };
+        for i in 0 .. ref_len + 1 {
+            if params.mode == AlnMode::Local {
+                for j in 0 .. query_len + 1 { consider( i, j ); }
+            } else if params.mode.ref_ends_free() {
+                consider( i, query_len );
             }
-            
-            Some( (Sequence(rev_r2) + fwd_r, Sequence(rev_q2) + fwd_q) ) },
+        }
+        if params.mode.query_ends_free() {
+            for j in 0 .. query_len + 1 { consider( ref_len, j ); }
+        } else {
+            consider( ref_len, query_len );
+        }
+    }
+    let end = best.map( |b| b.1 ).unwrap_or( (ref_len, query_len) );
+
+    // traceback, as trace
+    let (mut i, mut j) = end;
+    let mut core_r : Vec<Mmer> = Vec::new();
+    let mut core_q : Vec<Mmer> = Vec::new();
+    while (i > 0 || j > 0) && in_band( i, j ) {
+        match Cell::unpack_wide( &m[ at( i, j ) ] ).unwrap().0 {
+            AlnState::Match | AlnState::Mismatch if i > 0 && j > 0 => {
+                core_r.push( reference[(i - 1) as i32] );
+                core_q.push( query[(j - 1) as i32] );
+                i -= 1;
+                j -= 1;
+            },
+            AlnState::Del if i > 0 => {
+                core_r.push( reference[(i - 1) as i32] );
+                core_q.push( HYPHEN );
+                i -= 1;
+            },
+            AlnState::Ins if j > 0 => {
+                core_r.push( HYPHEN );
+                core_q.push( query[(j - 1) as i32] );
+                j -= 1;
+            },
+            _ => break
+        }
+    }
+    core_r.reverse();
+    core_q.reverse();
+
+    let mut padded_ref : Vec<Mmer> = Vec::with_capacity( reference.len() + query.len() );
+    let mut padded_query : Vec<Mmer> = Vec::with_capacity( reference.len() + query.len() );
+    pad_unaligned( &mut padded_ref, &mut padded_query, &reference.0[.. i as usize], &query.0[.. j as usize] );
+    padded_ref.extend( core_r );
+    padded_query.extend( core_q );
+    pad_unaligned( &mut padded_ref, &mut padded_query, &reference.0[end.0 as usize ..], &query.0[end.1 as usize ..] );
+    Ok( (Sequence(padded_ref), Sequence(padded_query)) )
+}
 
-        None => None
+/// aligned_columns -- column range of a padded alignment from the first to the last column
+///   where both sequences have a base; None if there is no such column
+pub fn aligned_columns( padded_ref: &Sequence, padded_query: &Sequence ) -> Option<(usize, usize)> {
+    let is_m = |k: &usize| padded_ref.0[*k] != HYPHEN && padded_query.0[*k] != HYPHEN;
+    let cols = min( padded_ref.len(), padded_query.len() );
+    match ((0 .. cols).find( &is_m ), (0 .. cols).rev().find( &is_m )) {
+        (Some(a), Some(b)) => Some((a, b + 1)),
+        _ => None
+    }
+}
+
+/// cigar -- CIGAR operations for a padded alignment
+///
+/// reference bases before the first aligned column are skipped and counted in the returned
+///   offset (ie. POS - 1 in SAM terms); unaligned query bases at either end are soft-clipped.
+///   Returns None if no query base is aligned to a reference base.
+///
+pub fn cigar( padded_ref: &Sequence, padded_query: &Sequence ) -> Option<(/*ref offset*/ usize, Vec<(usize, char)>)> {
+    let (a, b) = match aligned_columns( padded_ref, padded_query ) {
+        Some(x) => x,
+        None => return None
+    };
+    let mut ops : Vec<(usize, char)> = Vec::new();
+    let mut push = |op: char| {
+        let extend = match ops.last_mut() {
+            Some(last) if last.1 == op => { last.0 += 1; true },
+            _ => false
+        };
+        if !extend {
+            ops.push( (1, op) );
+        }
+    };
+
+    let offset = padded_ref.0[..a].iter().filter( |&&c| c != HYPHEN ).count();
+    for _ in padded_query.0[..a].iter().filter( |&&c| c != HYPHEN ) {
+        push('S');
+    }
+    for k in a .. b {
+        match (padded_ref.0[k] == HYPHEN, padded_query.0[k] == HYPHEN) {
+            (false, false) => push('M'),
+            (false, true) => push('D'),
+            (true, false) => push('I'),
+            (true, true) => ()
+        }
+    }
+    for _ in padded_query.0[b..].iter().filter( |&&c| c != HYPHEN ) {
+        push('S');
+    }
+    Some((offset, ops))
+}
+
+pub fn cigar_string( ops: &[(usize, char)] ) -> String {
+    ops.iter().map( |&(n, op)| format!("{}{}", n, op) ).collect()
+}
+
+/// edit_count -- mismatches plus inserted and deleted bases between the first and last
+///   aligned columns (the SAM NM tag)
+pub fn edit_count( padded_ref: &Sequence, padded_query: &Sequence ) -> usize {
+    match aligned_columns( padded_ref, padded_query ) {
+        Some((a, b)) => (a .. b).filter( |&k| !bases_match( padded_ref.0[k], padded_query.0[k] ) ).count(),
+        None => 0
+    }
+}
+
+/// score_padded -- score of a padded alignment under params, between the first and last
+///   aligned columns
+pub fn score_padded( padded_ref: &Sequence, padded_query: &Sequence, params: &AlnParams ) -> AlnScore {
+    let (a, b) = match aligned_columns( padded_ref, padded_query ) {
+        Some(x) => x,
+        None => return 0
+    };
+    let mut score = 0;
+    let mut prev = AlnState::Nil;
+    for k in a .. b {
+        let (r, q) = (padded_ref.0[k], padded_query.0[k]);
+        let state = if r == HYPHEN && q == HYPHEN {
+            continue
+        } else if r == HYPHEN {
+            AlnState::Ins
+        } else if q == HYPHEN {
+            AlnState::Del
+        } else if bases_match( r, q ) {
+            AlnState::Match
+        } else {
+            AlnState::Mismatch
+        };
+        score += match state {
+            AlnState::Match => params.equal,
+            AlnState::Mismatch => params.mismatch,
+            _ if state == prev => params.gap_ext,
+            _ => params.gap_open
+        };
+        prev = state;
     }
+    score
 }
@@ -15,19 +15,31 @@ use std::str;
 use std::fmt::Debug;
 use std::cmp::{PartialOrd,Ordering,max};
 use std::io::Read;
+use std::io::Write;
+use std::env;
+use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::mpsc::channel;
+use std::thread;
+use std::collections::BTreeMap;
 use bio::io::{fasta,fastq};
 
 use myxogast::align::*;
 use myxogast::tree::*;
 use myxogast::seq::*;
 use myxogast::matrix::*;
+use myxogast::sam;
+use myxogast::gaf;
+use myxogast::index::GraphIndex;
+use myxogast::consensus::Pileup;
+use myxogast::validate::Severity;
  
 use argparse::{ArgumentParser, StoreTrue, Store};
 
 // FIXME:
 const params : AlnParams = AlnParams {
-    llocal:    true,
-    rlocal:    true,
+    mode:      AlnMode::SemiGlobal,
     max_indel: None,
     gap_open:  -1,
     gap_ext:   -1,
@@ -35,55 +47,661 @@ const params : AlnParams = AlnParams {
     equal:     1 };
 
 
+/// Query - a query record; `qual` holds Phred scores (ASCII offset removed) for FASTQ input
+struct Query {
+    name: String,
+    seq:  Sequence,
+    qual: Option<Vec<u8>>
+}
 
+#[derive(Clone)]
+struct Opts {
+    params:        AlnParams,
+    edit_distance: bool,
+    max_hits:      usize,
+    use_qual:      bool,
+    posterior:     bool,
+    format:        String,
+    // k-mer index for seeded, banded alignment, and the band's half-width
+    index:         Option<Arc<GraphIndex>>,
+    band:          usize
+}
 
-fn main() {
-    let mut ref_fname : String = String::new();
-    let mut query_fname : String = String::new();
-    { // scope block?
-        let mut parser = ArgumentParser::new();
-        parser.refer(&mut ref_fname)
-            .add_argument("ref", Store, "refernce JSON file")
-            .required();
-        parser.refer(&mut query_fname)
-            .add_argument("query", Store, "query file")
-            .required();
-        parser.parse_args_or_exit();
+/// Aligned - the output line for one query, plus the reference it was placed on (SAM only)
+struct Aligned {
+    line:      String,
+    reference: Option<(String, usize)>
+}
+
+/// report_err -- note on stderr a query that couldn't be aligned; it is then written as
+///   unaligned
+fn report_err( read: &Query, e: AlnErr ) {
+    eprintln!("can't align {}: {:?}", read.name, e);
+}
+
+/// align_one -- align a single query against the graph
+fn align_one( graph: &SeqGraph, read: &Query, opts: &Opts ) -> Aligned {
+    if opts.edit_distance {
+        let (path, dist) = match graph.align__edit_distance( &read.seq, &opts.params ) {
+            Some(x) => x,
+            None => panic!("--edit-distance supports only global and semiglobal modes")
+        };
+        let repeats = sam::repeat_tag( graph, &path ).map( |t| format!(" repeats:{}", t) ).unwrap_or_default();
+        return Aligned { line: format!("name:{} - distance:{} path:{:?}{}", read.name, dist, path.nodes, repeats ), reference: None };
     }
 
+    let quals = read.qual.as_ref().map( |q| &q[..] );
+    let scoring_quals = if opts.use_qual { quals } else { None };
+
+    // (path, padded_ref, padded_query, score) for each hit, best first, and the MAPQ
+    let aligned : Result<(Vec<(Path, Sequence, Sequence, Option<i64>)>, u8), AlnErr> = if opts.max_hits > 1 {
+        graph.align__top_k( &read.seq, scoring_quals, &opts.params, opts.max_hits ).map( |(path, hits)| {
+            (hits.into_iter().map( |(score, r, q)| (path.clone(), r, q, Some(score)) ).collect(), sam::MAPQ_UNAVAILABLE)
+        })
+    } else if let Some(ref index) = opts.index {
+        graph.align__seeded( index, &read.seq, scoring_quals, &opts.params, opts.band )
+            .map( |(path, r, q)| (vec![ (path, r, q, None) ], sam::MAPQ_UNAVAILABLE) )
+    } else {
+        graph.align__global_max_scored::<AlnScore>( &read.seq, scoring_quals, &opts.params )
+            .map( |(path, tgt, refe, score, runner_up)| (vec![ (path, tgt, refe, None) ], sam::mapq( score, runner_up, &opts.params )) )
+    };
+    let (hits, mapq) = match aligned {
+        Ok(x) => x,
+        Err(e) => {
+            report_err( read, e );
+            (Vec::new(), sam::MAPQ_UNAVAILABLE)
+        }
+    };
+
+    let post = if opts.posterior { graph.posteriors( &read.seq, scoring_quals, &opts.params ) } else { None };
+    let post_tag = |path: &Path| post.as_ref().and_then( |p| sam::posterior_tag( graph, path, p ) );
+
+    let mut lines = Vec::new();
+    for (k, &(ref path, ref tgt, ref refe, score)) in hits.iter().enumerate() {
+        let xb = post_tag( path ).map( |t| format!("\tXB:Z:{}", t) ).unwrap_or_default();
+        lines.push( match opts.format.as_str() {
+            // unmapped records get no XB tag
+            "sam" if cigar( tgt, refe ).is_some() =>
+                sam::sam_record( graph, &read.name, &read.seq, quals, path, tgt, refe, mapq, k > 0, &opts.params ) + &xb,
+            "sam" => sam::sam_unmapped( &read.name, &read.seq, quals ),
+            "gaf" => gaf::gaf_record( graph, &read.name, &read.seq, path, tgt, refe, mapq, &opts.params )
+                .map( |rec| rec + &xb )
+                .unwrap_or_else( || gaf::gaf_unmapped( &read.name, &read.seq ) ),
+            _ => {
+                let mut nodes = sam::node_positions( graph, path, tgt, refe ).unwrap_or( "*".to_string() );
+                if let Some(t) = sam::repeat_tag( graph, path ) {
+                    nodes = format!("{} repeats:{}", nodes, t);
+                }
+                if let Some(t) = sam::optional_tag( graph, path ) {
+                    nodes = format!("{} optional:{}", nodes, t);
+                }
+                if let Some(t) = post_tag( path ) {
+                    nodes = format!("{} posterior:{}", nodes, t);
+                }
+                match score {
+                    Some(sc) => format!("name:{} hit:{} score:{} - {}, {} nodes:{}", read.name, k + 1, sc, tgt, refe, nodes ),
+                    None => format!("name:{} mapq:{} - {}, {} nodes:{}", read.name, mapq, tgt, refe, nodes )
+                }
+            }
+        });
+    }
+    if lines.is_empty() {
+        lines.push( match opts.format.as_str() {
+            "sam" => sam::sam_unmapped( &read.name, &read.seq, quals ),
+            "gaf" => gaf::gaf_unmapped( &read.name, &read.seq ),
+            _ => format!("name:{} - no hits", read.name)
+        });
+    }
+
+    Aligned {
+        line: lines.join("\n"),
+        reference: match (opts.format.as_str(), hits.first()) {
+            ("sam", Some(hit)) => Some( sam::sam_reference( graph, &hit.0 ) ),
+            _ => None
+        }
+    }
+}
+
+/// align_all -- run `work` (eg. align_one) on records on a pool of `threads` workers sharing
+///   one read-only graph, passing results to `emit` in input order as they become available
+fn align_all<T, F>( graph: Arc<SeqGraph>, records: Arc<Vec<Query>>, threads: usize, opts: &Opts,
+                    work: fn(&SeqGraph, &Query, &Opts) -> T, mut emit: F )
+    where T: Send + 'static, F: FnMut(T) {
+    let counter = Arc::new(AtomicUsize::new(0));
+    let (tx, rx) = channel();
+
+    let mut workers = Vec::new();
+    for _ in 0 .. max(threads, 1) {
+        let (graph, records, counter, tx, opts) = (graph.clone(), records.clone(), counter.clone(), tx.clone(), opts.clone());
+        workers.push( thread::spawn( move || {
+            loop {
+                let i = counter.fetch_add(1, AtomicOrdering::SeqCst);
+                if i >= records.len() {
+                    break;
+                }
+                tx.send( (i, work( &graph, &records[i], &opts )) ).unwrap();
+            }
+        }));
+    }
+    drop(tx);
+
+    // results arrive out of order; hold them until their predecessors have been emitted
+    let mut pending : BTreeMap<usize, T> = BTreeMap::new();
+    let mut next_out = 0;
+    for (i, aligned) in rx {
+        pending.insert(i, aligned);
+        while let Some(aligned) = pending.remove(&next_out) {
+            emit(aligned);
+            next_out += 1;
+        }
+    }
+    for w in workers {
+        w.join().unwrap();
+    }
+}
+
+
+fn read_graph( fname: &str ) -> SeqGraph {
     let mut graph_s = String::new();
-    File::open(ref_fname).unwrap().read_to_string(&mut graph_s);
-    let graph = SeqGraph::from_json( &graph_s ).unwrap();
-    println!("graph: {:?}", graph);
+    File::open(fname).unwrap().read_to_string(&mut graph_s);
+    SeqGraph::from_json( &graph_s ).unwrap()
+}
 
+/// read_queries -- the records of a FASTA or FASTQ file, by its extension
+fn read_queries( fname: &str ) -> Vec<Query> {
     // FIXME: it seems like Rust-Bio implements nearly IDENTICAL structures
     //   for records from FASTA and FASTQ files, but they're not the same types!
-    let query_lc = query_fname.to_lowercase();
-    let reader = if query_lc.ends_with(".fasta") || query_lc.ends_with(".fa") {
-        for gene in fasta::Reader::from_file(query_fname).unwrap().records() {
+    let query_lc = fname.to_lowercase();
+    let mut records = Vec::new();
+    if query_lc.ends_with(".fasta") || query_lc.ends_with(".fa") {
+        for gene in fasta::Reader::from_file(fname).unwrap().records() {
             let seq = gene.unwrap();
-            let gene_name = seq.id().unwrap();
+            let gene_name = seq.id().unwrap().to_string();
 
             // FIXME: ugh ..
             let seq_str : String = String::from_utf8_lossy( &seq.seq() ).into_owned();
-            println!("name:{}", gene_name);
             let query = Sequence::from_str(seq_str.as_str()).unwrap();
-            let (_, tgt, refe) = graph.align__global_max( &query, &params ).unwrap();
-            println!("name:{} - {}, {}", gene_name, tgt, refe );
+            records.push( Query { name: gene_name, seq: query, qual: None } );
         }
 
     } else if query_lc.ends_with(".fastq") || query_lc.ends_with(".fq") {
-        for gene in fastq::Reader::from_file(query_fname).unwrap().records() {
+        for gene in fastq::Reader::from_file(fname).unwrap().records() {
             let seq = gene.unwrap();
+            let gene_name = seq.id().unwrap().to_string();
 
             // FIXME: ugh ..
             let seq_str : String = String::from_utf8_lossy( &seq.seq() ).into_owned();
 
             let query = Sequence::from_str(seq_str.as_str()).unwrap();
+            // Phred+33
+            let qual = seq.qual().iter().map( |q| q.saturating_sub(33) ).collect();
+            records.push( Query { name: gene_name, seq: query, qual: Some(qual) } );
+        }
+
+    } else {
+        panic!("don't recognize file type: {}", fname);
+    }
+    records
+}
+
+/// parse a subcommand's arguments; `args[0]` is the command name, for usage messages
+fn parse_or_exit( parser: &ArgumentParser, args: Vec<String> ) {
+    match parser.parse( args, &mut io::stdout(), &mut io::stderr() ) {
+        Ok(()) => (),
+        Err(code) => process::exit(code)
+    }
+}
+
+/// myxo stats <ref> -- print a summary of the graph
+fn stats_main( args: Vec<String> ) {
+    let mut ref_fname : String = String::new();
+    {
+        let mut parser = ArgumentParser::new();
+        parser.set_description("summarise a graph-JSON reference");
+        parser.refer(&mut ref_fname)
+            .add_argument("ref", Store, "refernce JSON file")
+            .required();
+        parse_or_exit( &parser, args );
+    }
+
+    let st = read_graph( &ref_fname ).stats();
+    let len_s = |l: Option<usize>| l.map( |x| x.to_string() ).unwrap_or( "NA".to_string() );
+    println!("min_len\t{}", len_s( st.min_len ));
+    println!("max_len\t{}", len_s( st.max_len ));
+    println!("fragments\t{}", st.fragments);
+    println!("branches\t{}", st.branches);
+    println!("repeats\t{}", st.repeats);
+    println!("dists\t{}", st.dists);
+    println!("depth\t{}", st.depth);
+    println!("total_seq\t{}", st.total_seq);
+    println!("paths\t{}", st.paths);
+}
+
+/// myxo validate <ref> -- report problems with a graph-JSON reference; exits 1 on errors
+fn validate_main( args: Vec<String> ) {
+    let mut ref_fname : String = String::new();
+    {
+        let mut parser = ArgumentParser::new();
+        parser.set_description("check a graph-JSON reference for errors and likely mistakes");
+        parser.refer(&mut ref_fname)
+            .add_argument("ref", Store, "refernce JSON file")
+            .required();
+        parse_or_exit( &parser, args );
+    }
+
+    let mut graph_s = String::new();
+    File::open(ref_fname).unwrap().read_to_string(&mut graph_s);
+    let issues = SeqGraph::validate( &graph_s );
+    for issue in issues.iter() {
+        println!("{}", issue);
+    }
+    if issues.iter().any( |i| i.severity == Severity::Error ) {
+        process::exit(1);
+    }
+}
+
+/// myxo extract <ref> (--node NAME | --from NAME --to NAME) -- write part of a graph as
+///   graph-JSON, or its haplotypes as FASTA
+fn extract_main( args: Vec<String> ) {
+    let mut ref_fname : String = String::new();
+    let mut node = String::new();
+    let mut from = String::new();
+    let mut to = String::new();
+    let mut as_fasta = false;
+    {
+        let mut parser = ArgumentParser::new();
+        parser.set_description("extract a named node, or the region between two named nodes, as a new graph");
+        parser.refer(&mut ref_fname)
+            .add_argument("ref", Store, "refernce JSON file")
+            .required();
+        parser.refer(&mut node)
+            .add_option(&["--node"], Store, "the node (eg. a branch) to extract");
+        parser.refer(&mut from)
+            .add_option(&["--from"], Store, "first node of the region to extract");
+        parser.refer(&mut to)
+            .add_option(&["--to"], Store, "last node of the region to extract");
+        parser.refer(&mut as_fasta)
+            .add_option(&["--fasta"], StoreTrue, "write the region's haplotypes as FASTA rather than graph-JSON");
+        parse_or_exit( &parser, args );
+    }
+
+    let graph = read_graph( &ref_fname );
+    let sub = match (node.is_empty(), from.is_empty(), to.is_empty()) {
+        (false, true, true) => graph.subtree( &node ),
+        (true, false, false) => graph.between( &from, &to ),
+        _ => panic!("give either --node, or both --from and --to")
+    };
+    match sub {
+        Ok(g) => if as_fasta {
+            g.write_fasta( &mut io::stdout(), 60 ).unwrap();
+        } else {
+            println!("{}", g.to_json());
+        },
+        Err(e) => {
+            eprintln!("can't extract region: {:?}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// classify_one -- the member a single query is assigned to at each branch along its path
+fn classify_one( graph: &SeqGraph, read: &Query, opts: &Opts ) -> Vec<(u32, Option<u32>)> {
+    let quals = if opts.use_qual { read.qual.as_ref().map( |q| &q[..] ) } else { None };
+    match graph.align__global_max_qual( &read.seq, quals, &opts.params ) {
+        Ok((path, r, q)) => graph.classify( &path, &r, &q ),
+        Err(e) => {
+            report_err( read, e );
+            Vec::new()
+        }
+    }
+}
+
+/// myxo classify <ref> <query> -- assign each read to a member at each branch (eg. barcodes
+///   or alleles), writing the assignments, a table of reads per member, and optionally the
+///   reads split into a file per member
+fn classify_main( args: Vec<String> ) {
+    let mut ref_fname : String = String::new();
+    let mut query_fname : String = String::new();
+    let mut threads : usize = 1;
+    let mut use_qual = false;
+    let mut mode = "semiglobal".to_string();
+    let mut counts_fname = String::new();
+    let mut split = String::new();
+    {
+        let mut parser = ArgumentParser::new();
+        parser.set_description("assign reads to branch members; writes `read<TAB>branch=member,...` lines \
+                                (`*` where the read doesn't reach the branch)");
+        parser.refer(&mut ref_fname)
+            .add_argument("ref", Store, "refernce JSON file")
+            .required();
+        parser.refer(&mut query_fname)
+            .add_argument("query", Store, "query file")
+            .required();
+        parser.refer(&mut threads)
+            .add_option(&["-t", "--threads"], Store, "number of worker threads (default: 1)");
+        parser.refer(&mut use_qual)
+            .add_option(&["--qual"], StoreTrue, "scale match/mismatch scores by base quality (FASTQ only)");
+        parser.refer(&mut mode)
+            .add_option(&["-m", "--mode"], Store,
+                        "alignment mode: global, local, semiglobal (default), overlap or querylocal");
+        parser.refer(&mut counts_fname)
+            .add_option(&["--counts"], Store, "write the reads per member table here (default: stderr)");
+        parser.refer(&mut split)
+            .add_option(&["--split"], Store,
+                        "write reads to PREFIX<branch>.<member>.fasta (or .fastq), per member assigned");
+        parse_or_exit( &parser, args );
+    }
+
+    let graph = Arc::new( read_graph( &ref_fname ) );
+    let aln_mode = match AlnMode::from_name( &mode ) {
+        Some(m) => m,
+        None => panic!("unknown alignment mode: {}", mode)
+    };
+    let opts = Opts { params: AlnParams { mode: aln_mode, .. params }, edit_distance: false, max_hits: 1, use_qual: use_qual,
+                      posterior: false, format: "text".to_string(), index: None, band: 0 };
+    let records = Arc::new( read_queries( &query_fname ) );
+
+    // reads per (branch, member), None for reads not reaching the branch; every member is listed
+    let mut counts : BTreeMap<(u32, Option<u32>), usize> = BTreeMap::new();
+    for id in 0 .. graph.node_count() as u32 {
+        if let Some(&SeqNode::Branch { members: ref members, ..}) = graph.node( id ) {
+            counts.insert( (id, None), 0 );
+            for m in members {
+                counts.insert( (id, m.iden()), 0 );
+            }
+        }
+    }
+
+    let mut files : BTreeMap<(u32, u32), File> = BTreeMap::new();
+    let mut k = 0;
+    align_all( graph.clone(), records.clone(), threads, &opts, classify_one, |assigned| {
+        let (name, seq, qual) = (&records[k].name, &records[k].seq, &records[k].qual);
+        k += 1;
+        let tags : Vec<String> = assigned.iter().map( |&(id, m)| {
+            format!("{}={}", graph.node_name(id), m.map( |x| graph.node_name(x) ).unwrap_or( "*".to_string() ))
+        }).collect();
+        println!("{}\t{}", name, if tags.is_empty() { "*".to_string() } else { tags.join(",") });
+
+        for &(id, m) in assigned.iter() {
+            *counts.entry( (id, m) ).or_insert(0) += 1;
+            if let (false, Some(m)) = (split.is_empty(), m) {
+                let f = files.entry( (id, m) ).or_insert_with( || {
+                    let ext = if qual.is_some() { "fastq" } else { "fasta" };
+                    File::create( format!("{}{}.{}.{}", split, graph.node_name(id), graph.node_name(m), ext) ).unwrap()
+                });
+                match qual {
+                    &Some(ref q) => {
+                        let q : String = q.iter().map( |&x| (x + 33) as char ).collect();
+                        write!( f, "@{}\n{}\n+\n{}\n", name, seq, q ).unwrap()
+                    },
+                    &None => write!( f, ">{}\n{}\n", name, seq ).unwrap()
+                }
+            }
         }
+    });
 
+    let mut table = String::from("branch\tmember\treads\n");
+    for (&(id, m), n) in counts.iter() {
+        table += &format!("{}\t{}\t{}\n", graph.node_name(id), m.map( |x| graph.node_name(x) ).unwrap_or( "*".to_string() ), n);
+    }
+    if counts_fname.is_empty() {
+        eprint!("{}", table);
     } else {
-        panic!("don't recognize file type: {}", query_fname);
+        File::create( &counts_fname ).unwrap().write_all( table.as_bytes() ).unwrap();
+    }
+}
+
+/// capture_one -- the query bases (and qualities) aligned to each capture node along a
+///   single query's path
+fn capture_one( graph: &SeqGraph, read: &Query, opts: &Opts ) -> Vec<(u32, Sequence, Option<Vec<u8>>)> {
+    let quals = read.qual.as_ref().map( |q| &q[..] );
+    let scoring_quals = if opts.use_qual { quals } else { None };
+    let captured = graph.align__global_max_qual( &read.seq, scoring_quals, &opts.params )
+        .and_then( |(path, r, q)| graph.captures( &path, &r, &q, quals ) );
+    match captured {
+        Ok(c) => c,
+        Err(e) => {
+            report_err( read, e );
+            Vec::new()
+        }
+    }
+}
+
+/// myxo capture <ref> <query> -- the bases of each read aligned to the graph's capture nodes
+///   (eg. UMIs and barcodes), as a table or added to the reads' headers
+fn capture_main( args: Vec<String> ) {
+    let mut ref_fname : String = String::new();
+    let mut query_fname : String = String::new();
+    let mut threads : usize = 1;
+    let mut use_qual = false;
+    let mut mode = "semiglobal".to_string();
+    let mut headers = false;
+    {
+        let mut parser = ArgumentParser::new();
+        parser.set_description("extract the read bases aligned to capture nodes; writes `read<TAB>node<TAB>seq<TAB>qual` lines");
+        parser.refer(&mut ref_fname)
+            .add_argument("ref", Store, "refernce JSON file")
+            .required();
+        parser.refer(&mut query_fname)
+            .add_argument("query", Store, "query file")
+            .required();
+        parser.refer(&mut threads)
+            .add_option(&["-t", "--threads"], Store, "number of worker threads (default: 1)");
+        parser.refer(&mut use_qual)
+            .add_option(&["--qual"], StoreTrue, "scale match/mismatch scores by base quality (FASTQ only)");
+        parser.refer(&mut mode)
+            .add_option(&["-m", "--mode"], Store,
+                        "alignment mode: global, local, semiglobal (default), overlap or querylocal");
+        parser.refer(&mut headers)
+            .add_option(&["--headers"], StoreTrue,
+                        "write the reads instead, as FASTA or FASTQ with ` node=seq` added to each header");
+        parse_or_exit( &parser, args );
+    }
+
+    let graph = Arc::new( read_graph( &ref_fname ) );
+    let aln_mode = match AlnMode::from_name( &mode ) {
+        Some(m) => m,
+        None => panic!("unknown alignment mode: {}", mode)
     };
+    let opts = Opts { params: AlnParams { mode: aln_mode, .. params }, edit_distance: false, max_hits: 1, use_qual: use_qual,
+                      posterior: false, format: "text".to_string(), index: None, band: 0 };
+    let records = Arc::new( read_queries( &query_fname ) );
+    let phred = |q: &[u8]| -> String { q.iter().map( |&x| (x + 33) as char ).collect() };
+    // `*` for a node the read doesn't cover
+    let or_star = |x: String| if x.is_empty() { "*".to_string() } else { x };
+
+    let mut k = 0;
+    align_all( graph.clone(), records.clone(), threads, &opts, capture_one, |captured| {
+        let read = &records[k];
+        k += 1;
+        if headers {
+            let tags : String = captured.iter().map( |&(id, ref seq, _)| format!(" {}={}", graph.node_name(id), or_star( seq.to_string() )) ).collect();
+            match read.qual {
+                Some(ref q) => println!("@{}{}\n{}\n+\n{}", read.name, tags, read.seq, phred( q )),
+                None => println!(">{}{}\n{}", read.name, tags, read.seq)
+            }
+        } else {
+            for &(id, ref seq, ref qual) in captured.iter() {
+                println!("{}\t{}\t{}\t{}", read.name, graph.node_name(id), or_star( seq.to_string() ),
+                         or_star( qual.as_ref().map( |q| phred( q ) ).unwrap_or_default() ));
+            }
+        }
+    });
+}
 
+/// consensus_one -- a single query's best path and alignment
+fn consensus_one( graph: &SeqGraph, read: &Query, opts: &Opts ) -> Option<(Path, Sequence, Sequence)> {
+    let quals = if opts.use_qual { read.qual.as_ref().map( |q| &q[..] ) } else { None };
+    match graph.align__global_max_qual( &read.seq, quals, &opts.params ) {
+        Ok(aln) => Some(aln),
+        Err(e) => {
+            report_err( read, e );
+            None
+        }
+    }
+}
+
+/// myxo consensus <ref> <query> -- the consensus of the reads aligned to each fragment, as
+///   FASTA, with optionally a table of per-position depth and discordance
+fn consensus_main( args: Vec<String> ) {
+    let mut ref_fname : String = String::new();
+    let mut query_fname : String = String::new();
+    let mut threads : usize = 1;
+    let mut use_qual = false;
+    let mut mode = "semiglobal".to_string();
+    let mut table_fname = String::new();
+    {
+        let mut parser = ArgumentParser::new();
+        parser.set_description("call a consensus for each fragment from the reads aligned to it, written as FASTA");
+        parser.refer(&mut ref_fname)
+            .add_argument("ref", Store, "refernce JSON file")
+            .required();
+        parser.refer(&mut query_fname)
+            .add_argument("query", Store, "query file")
+            .required();
+        parser.refer(&mut threads)
+            .add_option(&["-t", "--threads"], Store, "number of worker threads (default: 1)");
+        parser.refer(&mut use_qual)
+            .add_option(&["--qual"], StoreTrue, "scale match/mismatch scores by base quality (FASTQ only)");
+        parser.refer(&mut mode)
+            .add_option(&["-m", "--mode"], Store,
+                        "alignment mode: global, local, semiglobal (default), overlap or querylocal");
+        parser.refer(&mut table_fname)
+            .add_option(&["--table"], Store,
+                        "also write `node<TAB>pos<TAB>ref<TAB>depth<TAB>discordance` lines (0-based positions) here");
+        parse_or_exit( &parser, args );
+    }
+
+    let graph = Arc::new( read_graph( &ref_fname ) );
+    let aln_mode = match AlnMode::from_name( &mode ) {
+        Some(m) => m,
+        None => panic!("unknown alignment mode: {}", mode)
+    };
+    let opts = Opts { params: AlnParams { mode: aln_mode, .. params }, edit_distance: false, max_hits: 1, use_qual: use_qual,
+                      posterior: false, format: "text".to_string(), index: None, band: 0 };
+    let records = Arc::new( read_queries( &query_fname ) );
+
+    let mut pileup = Pileup::new();
+    align_all( graph.clone(), records, threads, &opts, consensus_one, |aln| {
+        if let Some((path, r, q)) = aln {
+            pileup.add( &graph, &path, &r, &q );
+        }
+    });
+
+    let calls = pileup.consensus( &graph );
+    let mut table = String::from("node\tpos\tref\tdepth\tdiscordance\n");
+    for c in calls.iter() {
+        let mean_depth = c.depth.iter().sum::<usize>() as f64 / c.depth.len().max(1) as f64;
+        println!(">{} depth={:.1}\n{}", graph.node_name(c.id), mean_depth, c.seq);
+        if let Some(&SeqNode::Frag { val: ref val, .. }) = graph.node( c.id ) {
+            for (k, (&d, &x)) in c.depth.iter().zip( c.discordance.iter() ).enumerate() {
+                table += &format!("{}\t{}\t{}\t{}\t{:.3}\n", graph.node_name(c.id), k, base_to_char( val.0[k] ), d, x);
+            }
+        }
+    }
+    if !table_fname.is_empty() {
+        File::create( &table_fname ).unwrap().write_all( table.as_bytes() ).unwrap();
+    }
+}
+
+fn main() {
+    let args : Vec<String> = env::args().collect();
+    let sub_args = || {
+        let mut v = args[1..].to_vec();
+        v[0] = format!("{} {}", args[0], args[1]);
+        v
+    };
+    match args.get(1).map( |a| a.as_str() ) {
+        Some("stats") => stats_main( sub_args() ),
+        Some("validate") => validate_main( sub_args() ),
+        Some("extract") => extract_main( sub_args() ),
+        Some("classify") => classify_main( sub_args() ),
+        Some("capture") => capture_main( sub_args() ),
+        Some("consensus") => consensus_main( sub_args() ),
+        _ => align_main()
+    }
+}
+
+/// myxo <ref> <query> -- align reads to the graph
+fn align_main() {
+    let mut ref_fname : String = String::new();
+    let mut query_fname : String = String::new();
+    let mut edit_distance = false;
+    let mut threads : usize = 1;
+    let mut use_qual = false;
+    let mut format = "text".to_string();
+    let mut mode = "semiglobal".to_string();
+    let mut max_hits : usize = 1;
+    let mut posterior = false;
+    let mut seed : usize = 0;
+    let mut band : usize = 20;
+    { // scope block?
+        let mut parser = ArgumentParser::new();
+        parser.set_description("align reads to a graph-JSON reference.  Other commands: stats, validate, extract, classify, capture, consensus");
+        parser.refer(&mut ref_fname)
+            .add_argument("ref", Store, "refernce JSON file")
+            .required();
+        parser.refer(&mut query_fname)
+            .add_argument("query", Store, "query file")
+            .required();
+        parser.refer(&mut edit_distance)
+            .add_option(&["--edit-distance"], StoreTrue, "report unit-cost edit distance only (bit-parallel)");
+        parser.refer(&mut threads)
+            .add_option(&["-t", "--threads"], Store, "number of worker threads (default: 1)");
+        parser.refer(&mut use_qual)
+            .add_option(&["--qual"], StoreTrue, "scale match/mismatch scores by base quality (FASTQ only)");
+        parser.refer(&mut format)
+            .add_option(&["-f", "--format"], Store, "output format: text (default), sam or gaf");
+        parser.refer(&mut mode)
+            .add_option(&["-m", "--mode"], Store,
+                        "alignment mode: global, local, semiglobal (default), overlap or querylocal");
+        parser.refer(&mut max_hits)
+            .add_option(&["--max-hits"], Store,
+                        "report up to this many non-overlapping local alignments per read (default: 1)");
+        parser.refer(&mut posterior)
+            .add_option(&["--posterior"], StoreTrue,
+                        "report the posterior probability of each branch member taken (XB tag in SAM/GAF)");
+        parser.refer(&mut seed)
+            .add_option(&["--seed"], Store,
+                        "choose each read's path by its k-mers of this length, and align to it in a band (default: 0, off)");
+        parser.refer(&mut band)
+            .add_option(&["--band"], Store, "with --seed, diagonals to search either side of the seeds' (default: 20)");
+        parser.parse_args_or_exit();
+    }
+
+    let graph = Arc::new( read_graph( &ref_fname ) );
+    if format == "text" {
+        println!("graph: {:?}", graph);
+    } else if format != "sam" && format != "gaf" {
+        panic!("unknown output format: {}", format);
+    }
+    let aln_mode = match AlnMode::from_name( &mode ) {
+        Some(m) => m,
+        None => panic!("unknown alignment mode: {}", mode)
+    };
+    let opts = Opts { params: AlnParams { mode: aln_mode, .. params }, edit_distance: edit_distance, max_hits: max_hits, use_qual: use_qual,
+                      posterior: posterior, format: format,
+                      index: if seed > 0 { Some( Arc::new( GraphIndex::new( &graph, seed ) ) ) } else { None }, band: band };
+
+    let records = Arc::new( read_queries( &query_fname ) );
+
+    if opts.format == "sam" {
+        // the header has to list every reference path used, so hold records until the end
+        let mut refs : Vec<(String, usize)> = Vec::new();
+        let mut lines = Vec::new();
+        align_all( graph, records, threads, &opts, align_one, |aligned| {
+            if let Some(r) = aligned.reference {
+                if !refs.contains(&r) {
+                    refs.push(r);
+                }
+            }
+            lines.push(aligned.line);
+        });
+        print!("{}", sam::sam_header( &refs ));
+        for line in lines {
+            println!("{}", line);
+        }
+    } else {
+        align_all( graph, records, threads, &opts, align_one, |aligned| println!("{}", aligned.line) );
+    }
 }
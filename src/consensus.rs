@@ -0,0 +1,119 @@
+use std::collections::BTreeMap;
+use seq::*;
+use align::*;
+use tree::*;
+
+
+// the query bases aligned to one reference base: counts indexed by Mmer (A, T, G, C, a
+//   deletion, N), and the bases inserted after it, by how many reads inserted them
+#[derive(Clone, Debug, Default)]
+struct PileColumn {
+    counts: [usize; 6],
+    inserts: BTreeMap<Vec<Mmer>, usize>
+}
+
+/// Pileup: the query bases aligned to each position of each fragment, over many reads
+///   (which may take different paths)
+#[derive(Clone, Debug, Default)]
+pub struct Pileup {
+    nodes: BTreeMap<u32, Vec<PileColumn>>
+}
+
+/// NodeConsensus: the consensus of the reads aligned to one fragment
+///
+/// `depth` and `discordance` are per position of the fragment's own sequence: the reads
+///   covering it (with a base or a deletion), and the fraction of those (not counting Ns)
+///   disagreeing with the consensus there
+#[derive(Clone, Debug, PartialEq)]
+pub struct NodeConsensus {
+    pub id: u32,
+    pub seq: Sequence,
+    pub depth: Vec<usize>,
+    pub discordance: Vec<f64>
+}
+
+impl Pileup {
+    pub fn new() -> Pileup {
+        Pileup { nodes: BTreeMap::new() }
+    }
+
+    /// Pileup::add -- add one read's alignment to a path, as returned by the graph aligners.
+    ///   Only the aligned part of the read counts, and bases aligned to repeats are left out.
+    pub fn add(&mut self, graph: &SeqGraph, path: &Path, padded_ref: &Sequence, padded_query: &Sequence) {
+        let (a, b) = match aligned_columns( padded_ref, padded_query ) {
+            Some(x) => x,
+            None => return
+        };
+        let mut segs = Vec::new();
+        let mut seg_start = 0;
+        for (id, val) in GraphPath::from_graph( graph, path ).segments() {
+            if let Some(&SeqNode::Frag { .. }) = graph.node( id ) {
+                segs.push( (id, seg_start, seg_start + val.len()) );
+            }
+            seg_start += val.len();
+        }
+        let locate = |r: usize| segs.iter().find( |&&(_, s, e)| s <= r && r < e ).map( |&(id, s, e)| (id, r - s, e - s) );
+
+        let mut r = padded_ref.0[.. a].iter().filter( |&&c| c != HYPHEN ).count();
+        let mut inserted = Vec::new();
+        for k in a .. b {
+            let (rb, qb) = (padded_ref.0[k], padded_query.0[k]);
+            if rb == HYPHEN {
+                if qb != HYPHEN {
+                    inserted.push( qb );
+                }
+                continue;
+            }
+            // the first aligned column has a reference base, so r > 0 here
+            if !inserted.is_empty() {
+                if let Some((id, off, len)) = locate( r - 1 ) {
+                    *self.column( id, len, off ).inserts.entry( inserted.clone() ).or_insert(0) += 1;
+                }
+                inserted.clear();
+            }
+            if let Some((id, off, len)) = locate( r ) {
+                self.column( id, len, off ).counts[ qb as usize ] += 1;
+            }
+            r += 1;
+        }
+    }
+
+    fn column(&mut self, id: u32, len: usize, off: usize) -> &mut PileColumn {
+        &mut self.nodes.entry( id ).or_insert_with( || vec![ PileColumn::default(); len ] )[off]
+    }
+
+    /// Pileup::consensus -- the consensus of each fragment any read was aligned to, by id:
+    ///   at each position the commonest base (or deletion) among the reads, the fragment's
+    ///   own base winning ties and where no read has a base, followed by the commonest
+    ///   insertion if more than half of the reads there have it
+    pub fn consensus(&self, graph: &SeqGraph) -> Vec<NodeConsensus> {
+        self.nodes.iter().filter_map( |(&id, cols)| {
+            let val = match graph.node( id ) {
+                Some(&SeqNode::Frag { val: ref val, .. }) => val,
+                _ => return None
+            };
+            let mut seq = Vec::new();
+            let mut depth = Vec::with_capacity( cols.len() );
+            let mut discordance = Vec::with_capacity( cols.len() );
+            for (col, &own) in cols.iter().zip( val.0.iter() ) {
+                let votes : usize = col.counts[.. 5].iter().sum();
+                // ties go to the fragment's own base, then to the first in Mmer order
+                let call = if votes == 0 { own } else {
+                    (0 .. 5).max_by_key( |&x| (col.counts[x], x == own as usize, 5 - x) ).unwrap() as Mmer
+                };
+                if call != HYPHEN {
+                    seq.push( call );
+                }
+                let d : usize = col.counts.iter().sum();
+                depth.push( d );
+                discordance.push( if votes == 0 { 0. } else { 1. - col.counts[call as usize] as f64 / votes as f64 } );
+                if let Some((ins, &n)) = col.inserts.iter().max_by_key( |&(_, &n)| n ) {
+                    if 2 * n > d {
+                        seq.extend( ins.iter().cloned() );
+                    }
+                }
+            }
+            Some( NodeConsensus { id: id, seq: Sequence(seq), depth: depth, discordance: discordance } )
+        }).collect()
+    }
+}
@@ -0,0 +1,63 @@
+use seq::*;
+use align::*;
+use tree::*;
+use sam;
+
+
+/// gaf_record -- one GAF (Graph Alignment Format) line for a query aligned to a graph path
+///
+/// the path column lists only the fragments the alignment actually touches, as
+///   `>name>name...`, and path length/start/end are relative to the concatenation of those
+///   fragments.  Repeat copy counts, if any, are given in an `XR` tag, and whether optional
+///   nodes were taken in an `XO` tag.  Returns None if no query base is aligned.
+///
+pub fn gaf_record( graph: &SeqGraph, name: &str, query: &Sequence, path: &Path,
+                   padded_ref: &Sequence, padded_query: &Sequence, mapq: u8, params: &AlnParams ) -> Option<String> {
+    let (offset, ops) = match cigar( padded_ref, padded_query ) {
+        Some(x) => x,
+        None => return None
+    };
+
+    let count = |kinds: &str| ops.iter().filter( |&&(_, op)| kinds.contains(op) ).map( |&(n, _)| n ).sum::<usize>();
+    let lead_clip = match ops.first() { Some(&(n, 'S')) => n, _ => 0 };
+    let tail_clip = match ops.last() { Some(&(n, 'S')) => n, _ => 0 };
+    let ref_span = count("MD");
+    let block_len = count("MID");
+
+    let (start, end) = match aligned_columns( padded_ref, padded_query ) {
+        Some(x) => x,
+        None => return None
+    };
+    let matches = (start .. end).filter( |&k| padded_ref.0[k] != HYPHEN && bases_match( padded_ref.0[k], padded_query.0[k] ) ).count();
+
+    // keep the fragments overlapping [offset, offset + ref_span); zero-length ones (empty
+    //   members, skipped optionals) hold no aligned base
+    let spans : Vec<(u32, usize, usize)> = graph.node_spans( path, offset, offset + ref_span ).into_iter()
+        .filter( |&(_, start, end)| end > start )
+        .collect();
+    let path_s : String = spans.iter().map( |&(id, _, _)| format!(">{}", graph.node_name(id)) ).collect();
+    let segments = GraphPath::from_graph( graph, path ).segments();
+    let path_len : usize = spans.iter()
+        .map( |&(id, _, _)| segments.iter().find( |seg| seg.0 == id ).map( |seg| seg.1.len() ).unwrap_or(0) )
+        .sum();
+    let path_start = spans.first().map( |s| s.1 ).unwrap_or(0);
+
+    // GAF/PAF CIGARs don't carry clipping
+    let core_ops : Vec<(usize, char)> = ops.iter().cloned().filter( |&(_, op)| op != 'S' ).collect();
+
+    let xr = sam::repeat_tag( graph, path ).map( |t| format!("\tXR:Z:{}", t) ).unwrap_or_default();
+    let xo = sam::optional_tag( graph, path ).map( |t| format!("\tXO:Z:{}", t) ).unwrap_or_default();
+
+    Some( format!("{}\t{}\t{}\t{}\t+\t{}\t{}\t{}\t{}\t{}\t{}\t{}\tNM:i:{}\tAS:i:{}\tcg:Z:{}{}{}",
+                  name, query.len(), lead_clip, query.len() - tail_clip,
+                  path_s, path_len, path_start, path_start + ref_span,
+                  matches, block_len, mapq,
+                  edit_count( padded_ref, padded_query ),
+                  score_padded( padded_ref, padded_query, params ),
+                  cigar_string( &core_ops ), xr, xo) )
+}
+
+/// gaf_unmapped -- GAF line for a query with no alignment
+pub fn gaf_unmapped( name: &str, query: &Sequence ) -> String {
+    format!("{}\t{}\t*\t*\t*\t*\t*\t*\t*\t*\t*\t0", name, query.len())
+}
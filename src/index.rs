@@ -0,0 +1,228 @@
+use std::collections::{HashMap, HashSet};
+use seq::*;
+use align::*;
+use tree::*;
+
+
+/// Seed: where in the graph a k-mer starts, and every node it passes through (branches,
+///   the members taken, and zero-length fragments included), in order
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Seed {
+    pub id:     u32,      // fragment or repeat the k-mer starts in
+    pub offset: usize,    // offset into that node (a repeat's first copy)
+    pub nodes:  Vec<u32>
+}
+
+/// GraphIndex: the k-mers spelled by a graph's paths, including those spanning node and
+///   branch boundaries, for finding where in the graph a query may align
+pub struct GraphIndex {
+    pub k: usize,
+    kmers: HashMap<u64, Vec<Seed>>
+}
+
+// 2-bit code of a base; None for gaps
+fn base_code( b: Mmer ) -> Option<u64> {
+    if b < 4 { Some(b as u64) } else { None }
+}
+
+// the codes of the k-mers starting at `pos` of `node` (having taken `copies` copies of a
+//   repeat), each with the nodes it passes through, following every path out of node
+fn extend( node: &SeqNode, pos: usize, copies: usize, code: u64, need: usize, nodes: &mut Vec<u32>,
+           out: &mut Vec<(u64, Vec<u32>)> ) {
+    if need == 0 {
+        out.push( (code, nodes.clone()) );
+        return;
+    }
+    match node {
+        &SeqNode::Nil => (),
+        &SeqNode::Frag { val: ref val, next: ref next, ..} => {
+            if pos < val.len() {
+                if let Some(c) = base_code( val.0[pos] ) {
+                    extend( node, pos + 1, 0, (code << 2) | c, need - 1, nodes, out );
+                }
+            } else {
+                enter( next, code, need, nodes, out );
+            }
+        },
+        &SeqNode::Repeat { unit: ref unit, min: lo, max: hi, next: ref next, ..} => {
+            if pos == unit.len() && pos > 0 {
+                extend( node, 0, copies + 1, code, need, nodes, out );
+                return;
+            }
+            if pos == 0 && copies >= lo {
+                enter( next, code, need, nodes, out );
+            }
+            if pos < unit.len() && copies < hi {
+                if let Some(c) = base_code( unit.0[pos] ) {
+                    extend( node, pos + 1, copies, (code << 2) | c, need - 1, nodes, out );
+                }
+            }
+        },
+        &SeqNode::Branch { members: ref members, ..} => {
+            for m in members {
+                enter( m, code, need, nodes, out );
+            }
+        }
+    }
+}
+
+// the ids of the nodes from `node` up to `stop`, those in nested branches included
+fn member_ids( node: &SeqNode, stop: *const SeqNode, ids: &mut HashSet<u32> ) {
+    let mut node = node;
+    while node as *const SeqNode != stop {
+        node = match node {
+            &SeqNode::Nil => break,
+            &SeqNode::Frag { id, next: ref next, ..} | &SeqNode::Repeat { id, next: ref next, ..} => {
+                ids.insert( id );
+                next
+            },
+            &SeqNode::Branch { id, members: ref members, next: ref next, ..} => {
+                ids.insert( id );
+                for m in members {
+                    member_ids( m, &**next, ids );
+                }
+                next
+            }
+        };
+    }
+}
+
+// extend into the start of `node`
+fn enter( node: &SeqNode, code: u64, need: usize, nodes: &mut Vec<u32>, out: &mut Vec<(u64, Vec<u32>)> ) {
+    match node.iden() {
+        Some(id) => {
+            nodes.push( id );
+            extend( node, 0, 0, code, need, nodes, out );
+            nodes.pop();
+        },
+        None => extend( node, 0, 0, code, need, nodes, out )
+    }
+}
+
+impl GraphIndex {
+    /// GraphIndex::new -- index every k-mer of the graph by where it starts: each offset of
+    ///   each fragment, and of the first copy of each repeat, followed through every path
+    ///   from there.  k-mers holding gaps are left out.  k is at most 32.
+    pub fn new( graph: &SeqGraph, k: usize ) -> GraphIndex {
+        assert!( k > 0 && k <= 32, "k-mer length must be 1 to 32" );
+        let mut kmers : HashMap<u64, Vec<Seed>> = HashMap::new();
+        for id in 0 .. graph.node_count() as u32 {
+            let node = match graph.node( id ) { Some(n) => n, None => continue };
+            let starts = match node {
+                &SeqNode::Frag { val: ref val, ..} => val.len(),
+                &SeqNode::Repeat { unit: ref unit, max: hi, ..} if hi > 0 => unit.len(),
+                _ => 0
+            };
+            for offset in 0 .. starts {
+                let mut found = Vec::new();
+                extend( node, offset, 0, 0, k, &mut vec![ id ], &mut found );
+                for (code, nodes) in found {
+                    kmers.entry( code ).or_insert_with( Vec::new )
+                        .push( Seed { id: id, offset: offset, nodes: nodes } );
+                }
+            }
+        }
+        GraphIndex { k: k, kmers: kmers }
+    }
+
+    /// GraphIndex::lookup -- where in the graph a k-mer (of length k) occurs
+    pub fn lookup( &self, kmer: &[Mmer] ) -> &[Seed] {
+        let code = kmer.iter().fold( Some(0u64), |c, &b| c.and_then( |c| base_code( b ).map( |x| (c << 2) | x ) ) );
+        match code.and_then( |c| self.kmers.get( &c ) ) {
+            Some(seeds) if kmer.len() == self.k => &seeds[..],
+            _ => &[]
+        }
+    }
+
+    /// GraphIndex::seeds -- the (query offset, seed) of every k-mer of the query found in
+    ///   the graph
+    pub fn seeds( &self, query: &Sequence ) -> Vec<(usize, &Seed)> {
+        let mut hits = Vec::new();
+        if query.len() < self.k {
+            return hits;
+        }
+        for j in 0 .. query.len() - self.k + 1 {
+            for seed in self.lookup( &query.0[j .. j + self.k] ) {
+                hits.push( (j, seed) );
+            }
+        }
+        hits
+    }
+}
+
+impl SeqGraph {
+    /// SeqGraph::align__seeded -- align query to the path its k-mers point to, rather than
+    ///   testing every path: at each branch, the member holding the most of the query's
+    ///   seeds (the first, on a tie) is taken, and the query is aligned to that path only
+    ///   within `band` diagonals of those the seeds on it lie on (see align_banded).  An
+    ///   empty member holds the seeds passing from the branch straight on to what follows it.
+    ///
+    /// this is much faster than align__global_max on large graphs, but may miss the best
+    ///   path if the seeds are misleading.  Graphs with repeats, and queries with no seeds,
+    ///   are aligned by align__global_max_qual instead.
+    pub fn align__seeded(&self, index: &GraphIndex, query: &Sequence, quals: Option<&[u8]>, base_params: &AlnParams,
+                         band: usize )
+                         -> Result<(/*path*/ Path, /*padded_ref*/ Sequence, /*padded_query*/ Sequence), AlnErr> {
+        let hits = index.seeds( query );
+
+        // follow the members with the most votes
+        let mut path = Path::default();
+        let mut node = self.root();
+        loop {
+            node = match node {
+                &SeqNode::Nil => break,
+                &SeqNode::Frag { id, next: ref next, ..} => {
+                    path.nodes.push( id );
+                    next
+                },
+                &SeqNode::Repeat { .. } => return self.align__global_max_qual( query, quals, base_params ),
+                &SeqNode::Branch { id, members: ref members, next: ref next, ..} => {
+                    path.nodes.push( id );
+                    // a seed is in a member if it passes through one of the member's nodes,
+                    //   or enters the member from the branch (all an empty member has)
+                    let count = |m: &SeqNode| {
+                        let mut ids = HashSet::new();
+                        member_ids( m, &**next, &mut ids );
+                        let entered = m.iden();
+                        hits.iter().filter( |&&(_, seed)| {
+                            seed.nodes.iter().any( |x| ids.contains( x ) ) ||
+                                seed.nodes.windows( 2 ).any( |w| w[0] == id && Some(w[1]) == entered )
+                        }).count()
+                    };
+                    let mut best = &members[0];
+                    let mut best_count = count( best );
+                    for m in members.iter().skip(1) {
+                        let c = count( m );
+                        if c > best_count {
+                            best = m;
+                            best_count = c;
+                        }
+                    }
+                    best
+                }
+            };
+        }
+
+        // the diagonals of the seeds lying on that path
+        let on_path : HashSet<u32> = path.nodes.iter().cloned().collect();
+        let mut diags : Option<(i64, i64)> = None;
+        for &(j, seed) in hits.iter() {
+            if !seed.nodes.iter().all( |id| on_path.contains( id ) ) {
+                continue;
+            }
+            if let Some(i) = self.from_node( &path, seed.id, seed.offset ) {
+                let d = i as i64 - j as i64;
+                diags = Some( match diags { Some((lo, hi)) => (lo.min(d), hi.max(d)), None => (d, d) } );
+            }
+        }
+        let (lo, hi) = match diags {
+            Some(d) => d,
+            None => return self.align__global_max_qual( query, quals, base_params )
+        };
+
+        let full_ref = self.path_seq( &path );
+        let band = band as i64;
+        align_banded::<AlnScore>( &full_ref, query, quals, base_params, (lo - band, hi + band) )
+            .map( |(r, q)| (path, r, q) )
+    }
+}
@@ -3,7 +3,15 @@ extern crate rustc_serialize;
 pub mod matrix;
 pub mod seq;
 pub mod align;
+pub mod myers;
+pub mod sam;
+pub mod gaf;
 pub mod tree;
+pub mod validate;
+pub mod normalize;
+pub mod posterior;
+pub mod index;
+pub mod consensus;
 
 
 use align::*;
@@ -12,8 +20,7 @@ use seq::*;
 use matrix::*;
 
 const params : AlnParams = AlnParams {
-    llocal:    false,
-    rlocal:    false,
+    mode:      AlnMode::Global,
     max_indel: None,
     gap_open:  -1,
     gap_ext:   -1,
@@ -55,9 +62,713 @@ fn test_graph() {
     let loc_g = SeqGraph::from_json(r#"[{"branch": [["ATCG",{"branch":["TTGG","AAAA"]}],  ["ATGC","TTTT"]]}]"#).unwrap();
     let loc_q = Sequence::from_str("ATGCAAAA").unwrap();
 
+    // (path, padded reference, padded query); ties in the traceback go match, then
+    //   deletion, then insertion
     assert_eq!( loc_g.align__global_max( &loc_q, &params ),
-                Some((vec![24, 16, 15, 14], Sequence::from_str("A-TGCAAAA").unwrap(), Sequence::from_str("ATC-GAAAA").unwrap())));
+                Some((Path::from( vec![0, 1, 2, 4] ), Sequence::from_str("AT-CGAAAA").unwrap(), Sequence::from_str("ATGC-AAAA").unwrap())));
     
     assert_eq!( loc_g.align__local_max( &loc_q, &params ),
-                Some((vec![24, 23, 22], Sequence::from_str("ATGCAAAA").unwrap(), Sequence::from_str("ATGCTTTT").unwrap())) );
+                Some((Path::from( vec![0, 5, 6] ), Sequence::from_str("ATGCTTTT").unwrap(), Sequence::from_str("ATGCAAAA").unwrap())) );
+}
+
+
+#[test]
+fn test_myers() {
+    let s = |x: &str| Sequence::from_str(x).unwrap();
+
+    assert_eq!( myers::edit_distance( &s("ATGCATGC"), &s("ATGCATGC"), false, false ), 0 );
+    assert_eq!( myers::edit_distance( &s("ATGCATGC"), &s("ATGATGC"), false, false ), 1 );
+    assert_eq!( myers::edit_distance( &s("AAAAATGCTCGAAAAAAAA"), &s("TGCTCG"), false, false ), 13 );
+    assert_eq!( myers::edit_distance( &s("AAAAATGCTCGAAAAAAAA"), &s("TGCTCG"), true, true ), 0 );
+    assert_eq!( myers::edit_distance( &s("AAAAATGCTCGAAAAAAAA"), &s("TGCACG"), true, true ), 1 );
+    assert_eq!( myers::edit_distance( &s("ATGC"), &s(""), false, false ), 4 );
+
+    // multi-word queries
+    let long_ref = s( &"ATGGC".repeat(40) );
+    let mut long_q = long_ref.clone();
+    long_q.0.remove(70);
+    long_q.0[150] = 0;
+    assert_eq!( myers::edit_distance( &long_ref, &long_q, false, false ), 2 );
+
+    let g = SeqGraph::from_json(r#"[{"branch": [["ATCG",{"branch":["TTGG","AAAA"]}],  ["ATGC","TTTT"]]}]"#).unwrap();
+    let (_, d) = g.align__edit_distance( &s("ATCGAAAA"), &params ).unwrap();
+    assert_eq!( d, 0 );
+    let (_, d) = g.align__edit_distance( &s("ATGCAATT"), &params ).unwrap();
+    assert_eq!( d, 2 );
+}
+
+
+#[test]
+fn test_graph_shared() {
+    use std::sync::Arc;
+    use std::thread;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<SeqGraph>();
+
+    let g = Arc::new( SeqGraph::from_json(r#"[{"branch": [["ATCG",{"branch":["TTGG","AAAA"]}],  ["ATGC","TTTT"]]}]"#).unwrap() );
+    let expected = g.align__global_max( &Sequence::from_str("ATGCTTTT").unwrap(), &params );
+
+    let handles : Vec<_> = (0 .. 4).map( |_| {
+        let g = g.clone();
+        thread::spawn( move || g.align__global_max( &Sequence::from_str("ATGCTTTT").unwrap(), &params ) )
+    }).collect();
+    for h in handles {
+        assert_eq!( h.join().unwrap(), expected );
+    }
+}
+
+
+#[test]
+fn test_qual() {
+    assert_eq!( phred_scale( 10, 40 ), 10 );
+    assert_eq!( phred_scale( 10, 10 ), 9 );
+    assert_eq!( phred_scale( -10, 0 ), 0 );
+
+    let qparams = AlnParams { equal: 10, mismatch: -10, gap_open: -15, gap_ext: -15, .. params };
+
+    // the read matches the TTGG member but for one base; if the three bases that disagree
+    //   with the AAAA member are unreliable, AAAA is the better explanation
+    let g = SeqGraph::from_json(r#"[{"branch": [["ATCG",{"branch":["TTGG","AAAA"]}],  ["ATGC","TTTT"]]}]"#).unwrap();
+    let q = Sequence::from_str("ATCGTTGA").unwrap();
+    let (path, _, _) = g.align__global_max_qual( &q, None, &qparams ).unwrap();
+    let (qpath, _, _) = g.align__global_max_qual( &q, Some(&[40, 40, 40, 40, 40, 40, 40, 40]), &qparams ).unwrap();
+    assert_eq!( path, qpath );
+    let (lowq_path, r, _) = g.align__global_max_qual( &q, Some(&[40, 40, 40, 40, 0, 0, 0, 40]), &qparams ).unwrap();
+    assert!( lowq_path != path );
+    assert_eq!( r, Sequence::from_str("ATCGAAAA").unwrap() );
+
+    // qualities must be one per query base
+    assert_eq!( align_cells::<AlnScore>( &q, &q, Some(&[40, 40]), &qparams ), Err(AlnErr::QualLength) );
+    assert_eq!( align_cells::<AlnScore>( &q, &q, Some(&[40; 9]), &qparams ), Err(AlnErr::QualLength) );
+    assert_eq!( align_banded::<AlnScore>( &q, &q, Some(&[40; 9]), &qparams, (0, 0) ), Err(AlnErr::QualLength) );
+    assert_eq!( g.align__global_max_qual( &q, Some(&[40; 7]), &qparams ), Err(AlnErr::QualLength) );
+    assert_eq!( g.posteriors( &q, Some(&[40; 7]), &qparams ), None );
+}
+
+
+#[test]
+fn test_sam() {
+    let s = |x: &str| Sequence::from_str(x).unwrap();
+
+    assert_eq!( cigar( &s("AAATGC-TCGAA"), &s("--ATGCATC---") ), Some((2, vec![(4, 'M'), (1, 'I'), (2, 'M')])) );
+    assert_eq!( cigar( &s("--ATGC"), &s("GGATGC") ), Some((0, vec![(2, 'S'), (4, 'M')])) );
+    assert_eq!( cigar( &s("ATGC--"), &s("----AT") ), None );
+    assert_eq!( cigar_string( &[(2, 'S'), (4, 'M')] ), "2S4M" );
+    assert_eq!( edit_count( &s("AAATGC-TCGAA"), &s("--ATGCATA---") ), 2 );
+    assert_eq!( score_padded( &s("AAATGC--TCGAA"), &s("--ATGCAATC---"), &params ), 4 );
+
+    let g = SeqGraph::from_json(r#"[{"id": "pre", "seq": "AAAA"}, {"branch": [{"id": "A", "seq": "TTTTGC"}, {"id": "B", "seq": "GGGGCA"}]}]"#).unwrap();
+    let q = s("AAGGGGCA");
+    let lparams = AlnParams { mode: AlnMode::SemiGlobal, .. params };
+    let (path, _, _) = g.align__global_max( &q, &lparams ).unwrap();
+    assert_eq!( sam::sam_reference( &g, &path ), ("pre|B".to_string(), 10) );
+    let rec = sam::sam_record( &g, "r1", &q, None, &path, &s("AAAAGGGGCA"), &s("--AAGGGGCA"),
+                               sam::MAPQ_UNAVAILABLE, false, &lparams );
+    let fields : Vec<&str> = rec.split('\t').collect();
+    assert_eq!( &fields[..6], &["r1", "0", "pre|B", "3", "255", "8M"] );
+    assert_eq!( &fields[9..13], &["AAGGGGCA", "*", "AS:i:8", "NM:i:0"] );
+    assert_eq!( sam::sam_unmapped( "r2", &s("ACG"), Some(&[0, 10, 40]) ), "r2\t4\t*\t0\t0\t*\t*\t0\t0\tACG\t!+I" );
+}
+
+
+#[test]
+fn test_gaf() {
+    let s = |x: &str| Sequence::from_str(x).unwrap();
+    let lparams = AlnParams { mode: AlnMode::SemiGlobal, .. params };
+
+    let g = SeqGraph::from_json(r#"[{"id": "pre", "seq": "AAAA"}, {"branch": [{"id": "A", "seq": "TTTTGC"}, {"id": "B", "seq": "GGGGCA"}]}, {"id": "post", "seq": "CCCC"}]"#).unwrap();
+    let q = s("TGGGGCA");
+    let (path, _, _) = g.align__global_max( &q, &lparams ).unwrap();
+
+    // starts in the last base of "pre", skips "post" entirely
+    let rec = gaf::gaf_record( &g, "r1", &q, &path, &s("AAAAGGGGCACCCC"), &s("---TGGGGCA----"), 255, &lparams ).unwrap();
+    let fields : Vec<&str> = rec.split('\t').collect();
+    assert_eq!( fields, vec!["r1", "7", "0", "7", "+", ">pre>B", "10", "3", "10", "6", "7", "255",
+                             "NM:i:1", "AS:i:5", "cg:Z:7M"] );
+
+    let q2 = s("TGGGGACAT");
+    let rec = gaf::gaf_record( &g, "r2", &q2, &path, &s("AAAA-GGGG-CACCCC"), &s("----TGGGGACA-T--"), 255, &lparams ).unwrap();
+    let fields : Vec<&str> = rec.split('\t').collect();
+    assert_eq!( &fields[1..11], &["9", "1", "9", "+", ">B>post", "10", "0", "8", "6", "9"] );
+    assert_eq!( fields[14], "cg:Z:4M1I2M1D1M" );
+
+    // the empty member the read takes holds no base, so isn't in the path column
+    let g = SeqGraph::from_json(r#"[{"id": "pre", "seq": "AAAACG"}, {"branch": [{"id": "A", "seq": "TTTT"}, ""]}, {"id": "post", "seq": "GTCCCC"}]"#).unwrap();
+    let q = s("ACGGTCC");
+    let (path, r, pq) = g.align__global_max( &q, &lparams ).unwrap();
+    assert_eq!( path.nodes.len(), 4 );
+    let rec = gaf::gaf_record( &g, "r3", &q, &path, &r, &pq, 255, &lparams ).unwrap();
+    let fields : Vec<&str> = rec.split('\t').collect();
+    assert_eq!( &fields[5..9], &[">pre>post", "12", "3", "10"] );
+}
+
+
+#[test]
+fn test_cell_width() {
+    assert_eq!( Cell::<i16>::unpack( &Cell::pack( &AlnState::Del, &-4095i16 ) ).unwrap(), (AlnState::Del, -4095) );
+    assert_eq!( Cell::<i16>::try_pack( &AlnState::Match, 4096 ), Err(AlnErr::Overflow) );
+    assert_eq!( Cell::<i64>::unpack( &Cell::pack( &AlnState::Match, &(1i64 << 40) ) ).unwrap(), (AlnState::Match, 1 << 40) );
+    assert!( Cell::pack( &AlnState::Match, &-5 ) < Cell::pack( &AlnState::Nil, &3 ) );
+
+    let reference = Sequence::from_str( &"ATGC".repeat(300) ).unwrap();
+    let big = AlnParams { equal: 10, .. params };
+    assert_eq!( align_cells::<i16>( &reference, &reference, None, &big ), Err(AlnErr::Overflow) );
+    let (r, q) = align_cells::<i64>( &reference, &reference, None, &big ).unwrap();
+    assert_eq!( (&r, &q), (&reference, &reference) );
+    assert_eq!( align_cells::<i32>( &reference, &reference, None, &big ).unwrap(), (r, q) );
+
+    let g = SeqGraph::from_json( &format!(r#"["{}", {{"branch": ["AAAA", "TTTT"]}}]"#, "ATGC".repeat(300)) ).unwrap();
+    let q = Sequence::from_str( &("ATGC".repeat(300) + "TTTT") ).unwrap();
+    assert_eq!( g.align__global_max_cells::<i16>( &q, None, &big ), Err(AlnErr::Overflow) );
+    assert_eq!( g.align__global_max_cells::<i64>( &q, None, &big ).unwrap().2, q );
+}
+
+
+#[test]
+fn test_aln_modes() {
+    let s = |x: &str| Sequence::from_str(x).unwrap();
+    let aln = |r: &str, q: &str, mode: AlnMode| {
+        let (pr, pq) = align( &s(r), &s(q), &AlnParams { mode: mode, .. params } ).unwrap();
+        let c = cigar( &pr, &pq );
+        (pr, pq, c)
+    };
+
+    assert_eq!( aln( "ATGC", "GGATGCGG", AlnMode::Global ), (s("--ATGC--"), s("GGATGCGG"), Some((0, vec![(2, 'S'), (4, 'M'), (2, 'S')]))) );
+    assert_eq!( aln( "ATGC", "GGATGCGG", AlnMode::QueryLocal ).2, Some((0, vec![(2, 'S'), (4, 'M'), (2, 'S')])) );
+    assert_eq!( aln( "GGATGCGG", "ATGC", AlnMode::SemiGlobal ), (s("GGATGCGG"), s("--ATGC--"), Some((2, vec![(4, 'M')]))) );
+    assert_eq!( aln( "GGGGATGCAGGGG", "TTATGCATT", AlnMode::Local ).2, Some((4, vec![(2, 'S'), (5, 'M'), (2, 'S')])) );
+    assert_eq!( aln( "AAAACCGT", "CCGTTTTT", AlnMode::Overlap ).2, Some((4, vec![(4, 'M'), (4, 'S')])) );
+
+    // a local alignment never scores below zero, even if nothing matches
+    assert_eq!( aln( "AAAA", "TTTT", AlnMode::Local ).2, None );
+
+    let g = SeqGraph::from_json(r#"["GG", {"branch": ["ATGC", "TTAA"]}, "GG"]"#).unwrap();
+    let (_, r, q) = g.align__global_max( &s("CCATGCCC"), &AlnParams { mode: AlnMode::Local, .. params } ).unwrap();
+    assert_eq!( (r, q), (s("GG--ATGCGG--"), s("--CCATGC--CC")) );
+}
+
+
+#[test]
+fn test_top_k() {
+    let s = |x: &str| Sequence::from_str(x).unwrap();
+
+    let reference = s("GGATGCATTTTTTATGCATTTTTCCATGGATCC");
+    let hits = align_top_k::<i32>( &reference, &s("ATGCAT"), None, &params, 4 ).unwrap();
+    let scores : Vec<i64> = hits.iter().map( |h| h.0 ).collect();
+    assert_eq!( &scores[..2], &[6, 6] );
+    assert!( scores[2] < 6 );
+
+    // the two exact copies, at different reference positions
+    let offsets : Vec<usize> = hits[..2].iter().map( |h| cigar( &h.1, &h.2 ).unwrap().0 ).collect();
+    assert_eq!( offsets, vec![2, 13] );
+
+    assert_eq!( align_top_k::<i32>( &s("AAAA"), &s("TTTT"), None, &params, 3 ).unwrap().len(), 0 );
+
+    let g = SeqGraph::from_json(r#"["ATGCATGG", {"branch": ["CCCC", "ATGCAT"]}]"#).unwrap();
+    let (path, hits) = g.align__top_k( &s("ATGCAT"), None, &params, 2 ).unwrap();
+    assert_eq!( g.path_seq( &path ), s("ATGCATGGATGCAT") );
+    assert_eq!( hits.iter().map( |h| h.0 ).collect::<Vec<i64>>(), vec![6, 6] );
+}
+
+
+#[test]
+fn test_paths() {
+    let s = |x: &str| Sequence::from_str(x).unwrap();
+
+    let g = SeqGraph::from_json(r#"["AA", {"branch": [{"id": "x", "seq": "C"}, ["G", {"branch": ["T", "TT"]}]]}, "AA"]"#).unwrap();
+    let seqs : Vec<Sequence> = g.paths().map( |h| h.seq ).collect();
+    assert_eq!( seqs, vec![ s("AACAA"), s("AAGTAA"), s("AAGTTAA") ] );
+    assert_eq!( g.path_count(), 3 );
+
+    for h in g.paths() {
+        assert_eq!( g.path_seq( &h.path ), h.seq );
+        assert_eq!( g.path_name( &h.path, "|" ), h.name );
+    }
+    assert!( g.paths().next().unwrap().name.contains("|x|") );
+
+    // shared tails: 2^40 paths, counted without enumerating them
+    let wide = format!("[{}]", vec![r#"{"branch": ["A", "C"]}"#; 40].join(", "));
+    assert_eq!( SeqGraph::from_json( &wide ).unwrap().path_count(), 1u128 << 40 );
+
+    let empty = SeqGraph::from_json(r#"["AA", {"branch": []}]"#).unwrap();
+    assert_eq!( empty.path_count(), 0 );
+    assert_eq!( empty.paths().count(), 0 );
+
+    let mut fasta = Vec::new();
+    SeqGraph::from_json(r#"[{"id": "a", "seq": "ATGCA"}, {"branch": [{"id": "b", "seq": "GG"}]}]"#).unwrap()
+        .write_fasta( &mut fasta, 3 ).unwrap();
+    assert_eq!( String::from_utf8(fasta).unwrap(), ">a|b\nATG\nCAG\nG\n" );
+}
+
+
+#[test]
+fn test_stats() {
+    let g = SeqGraph::from_json(r#"["AA", {"branch": ["C", ["G", {"branch": ["T", "TTT"]}]]}, {"branch": ["A", "CC"]}, "AA"]"#).unwrap();
+    assert_eq!( g.stats(), GraphStats { min_len: Some(6), max_len: Some(10), fragments: 8, branches: 3, repeats: 0, dists: 0,
+                                        depth: 2, total_seq: 13, paths: 6 } );
+    assert_eq!( g.max_len(), 10 );
+
+    // an empty branch has no paths, rather than panicking
+    let empty = SeqGraph::from_json(r#"["AA", {"branch": []}]"#).unwrap();
+    assert_eq!( empty.max_len(), 0 );
+    let st = empty.stats();
+    assert_eq!( (st.min_len, st.max_len, st.fragments, st.branches, st.paths), (None, None, 1, 1, 0) );
+
+    let partial = SeqGraph::from_json(r#"[{"branch": [{"branch": []}, "ATG"]}, "C"]"#).unwrap();
+    assert_eq!( (partial.stats().min_len, partial.stats().max_len), (Some(4), Some(4)) );
+}
+
+
+#[test]
+fn test_validate() {
+    use validate::*;
+
+    let ok = r#"[{"id": "a", "seq": "ATGC"}, {"id": "b", "branch": ["GG", {"id": "c", "seq": "TT"}]}, "CC"]"#;
+    assert_eq!( SeqGraph::validate( ok ), vec![] );
+
+    let bad = r#"[{"id": "a", "seq": "ATGC", "colour": "red"},
+                  {"id": "a", "branch": ["GG", {"id": "x", "seq": "gg"}, ""]},
+                  {"branch": []},
+                  {"dist": [{"A": 0.5, "T": 0.4}]},
+                  "ATQ"]"#;
+    let found : Vec<(Severity, String)> = SeqGraph::validate( bad ).into_iter()
+        .map( |i| (i.severity, i.location) )
+        .collect();
+    assert_eq!( found, vec![ (Severity::Warning, "/0".to_string()),            // unknown key
+                             (Severity::Warning, "/1".to_string()),            // duplicate name
+                             (Severity::Warning, "/1/branch/1".to_string()),   // identical members
+                             (Severity::Error, "/2/branch".to_string()),       // empty branch
+                             (Severity::Error, "/3/dist".to_string()),         // unsupported
+                             (Severity::Error, "/3/dist/0".to_string()),       // sums to 0.9
+                             (Severity::Error, "/4".to_string()) ] );          // bad base
+
+    assert_eq!( SeqGraph::validate( "[\"AT\"" )[0].severity, Severity::Error );
+}
+
+
+#[test]
+fn test_node_ids() {
+    let g = SeqGraph::from_json(r#"[{"id": "domain_1", "seq": "ATGC"},
+                                    {"id": "domain_2", "branch": [{"id": "A", "seq": "GG"}, ["TT", "CC"]]},
+                                    {"id": "domain_3", "seq": "AAAA"}]"#).unwrap();
+
+    // contiguous, in the order of the JSON text, with branch names on the branch itself
+    assert_eq!( g.node_count(), 6 );
+    let named : Vec<(&str, u32)> = ["domain_1", "domain_2", "A", "domain_3"].iter()
+        .map( |&n| (n, g.node_id(n).unwrap()) )
+        .collect();
+    assert_eq!( named, vec![ ("domain_1", 0), ("domain_2", 1), ("A", 2), ("domain_3", 5) ] );
+    assert_eq!( g.node_id("domain_4"), None );
+    assert_eq!( g.name(3), None );
+
+    match g.node(1) {
+        Some(&SeqNode::Branch { ref members, .. }) => assert_eq!( members.len(), 2 ),
+        n => panic!("expected a branch, got {:?}", n)
+    }
+    match g.node(4) {
+        Some(&SeqNode::Frag { ref val, .. }) => assert_eq!( *val, Sequence::from_str("CC").unwrap() ),
+        n => panic!("expected a fragment, got {:?}", n)
+    }
+    assert!( g.node(6).is_none() );
+
+    // lists nested in a branch run on into the sequence after it
+    let seqs : Vec<String> = g.paths().map( |h| format!("{}", h.seq) ).collect();
+    assert_eq!( seqs, vec![ "ATGCGGAAAA", "ATGCTTCCAAAA" ] );
+
+    // ids don't depend on what follows
+    let g2 = SeqGraph::from_json(r#"[{"id": "domain_1", "seq": "ATGC"}, {"id": "domain_2", "branch": ["GG", "TT"]}]"#).unwrap();
+    assert_eq!( g2.node_id("domain_2"), Some(1) );
+
+    assert!( SeqGraph::from_json(r#"[{"id": 3, "seq": "AT"}]"#).is_err() );
+    for bad in [r#"["ATXG"]"#, r#"[{"seq": "ATXG"}]"#, r#"[{"repeat": "CXG", "min": 1, "max": 2}]"#].iter() {
+        assert!( match SeqGraph::from_json( bad ) { Err(SeqErr::BadSequence(_)) => true, _ => false } );
+    }
+}
+
+
+#[test]
+fn test_coords() {
+    let s = |x: &str| Sequence::from_str(x).unwrap();
+
+    let g = SeqGraph::from_json(r#"[{"id": "pre", "seq": "AAAA"}, {"branch": [{"id": "A", "seq": "TTTTGC"}, {"id": "B", "seq": "GGGGCA"}]}, {"id": "post", "seq": "CCCC"}]"#).unwrap();
+    let (pre, b, post) = (g.node_id("pre").unwrap(), g.node_id("B").unwrap(), g.node_id("post").unwrap());
+    let path = Path::from( vec![ pre, 1, b, post ] );
+
+    assert_eq!( g.to_node( &path, 0 ), Some((pre, 0)) );
+    assert_eq!( g.to_node( &path, 4 ), Some((b, 0)) );
+    assert_eq!( g.to_node( &path, 13 ), Some((post, 3)) );
+    assert_eq!( g.to_node( &path, 14 ), None );
+    for offset in 0 .. 14 {
+        let (id, node_offset) = g.to_node( &path, offset ).unwrap();
+        assert_eq!( g.from_node( &path, id, node_offset ), Some(offset) );
+    }
+    assert_eq!( g.from_node( &path, g.node_id("A").unwrap(), 0 ), None );
+    assert_eq!( g.from_node( &path, b, 6 ), None );
+
+    assert_eq!( g.node_spans( &path, 2, 12 ), vec![ (pre, 2, 4), (b, 0, 6), (post, 0, 2) ] );
+    assert_eq!( sam::node_positions( &g, &path, &s("AAAAGGGGCACCCC"), &s("---AGGGG------") ),
+                Some("pre:3-4,B:0-4".to_string()) );
+}
+
+
+#[test]
+fn test_subgraph() {
+    let g = SeqGraph::from_json(r#"[{"id": "pre", "seq": "AAAA"},
+                                    {"id": "lib", "branch": [{"id": "A", "seq": "TTTTGC"}, ["GG", {"id": "B", "seq": "CA"}]]},
+                                    "TT",
+                                    {"id": "post", "seq": "CCCC"}]"#).unwrap();
+
+    // round trip
+    let g2 = SeqGraph::from_json( &g.to_json() ).unwrap();
+    assert_eq!( g2.paths().collect::<Vec<Haplotype>>(), g.paths().collect::<Vec<Haplotype>>() );
+
+    let lib = g.subtree("lib").unwrap();
+    let seqs : Vec<String> = lib.paths().map( |h| format!("{}", h.seq) ).collect();
+    assert_eq!( seqs, vec![ "TTTTGC", "GGCA" ] );
+    assert_eq!( (lib.node_id("lib"), lib.node_id("A"), lib.node_id("B"), lib.node_id("pre")), (Some(0), Some(1), Some(3), None) );
+    assert!( SeqGraph::validate( &lib.to_json() ).is_empty() );
+
+    let mid = g.between("lib", "post").unwrap();
+    let seqs : Vec<String> = mid.paths().map( |h| format!("{}", h.seq) ).collect();
+    assert_eq!( seqs, vec![ "TTTTGCTTCCCC", "GGCATTCCCC" ] );
+
+    let head = g.between("pre", "pre").unwrap();
+    assert_eq!( head.paths().next().unwrap().name, "pre" );
+
+    assert!( match g.between("post", "pre") { Err(SeqErr::NoPath) => true, _ => false } );
+    assert!( match g.between("pre", "B") { Err(SeqErr::NoPath) => true, _ => false } );
+    let tail = g.between("B", "post").unwrap();
+    assert_eq!( tail.paths().map( |h| format!("{}", h.seq) ).collect::<Vec<String>>(), vec![ "CATTCCCC" ] );
+    assert!( match g.subtree("nope") { Err(SeqErr::NotFound) => true, _ => false } );
+}
+
+
+#[test]
+fn test_normalize() {
+    let seqs = |g: &SeqGraph| {
+        let mut v : Vec<String> = g.paths().map( |h| format!("{}", h.seq) ).collect();
+        v.sort();
+        v
+    };
+
+    // adjacent fragments merge
+    let (g, map) = SeqGraph::from_json(r#"[{"id": "a", "seq": "ATGC"}, {"id": "b", "seq": "ATGC"}]"#).unwrap().normalize();
+    assert_eq!( g.node_count(), 1 );
+    assert_eq!( (map["a"].clone(), map["b"].clone()), (vec![0], vec![0]) );
+    assert_eq!( g.node_id("a"), Some(0) );
+
+    // shared prefixes and suffixes are hoisted out, and the fragment before absorbs the prefix
+    let orig = SeqGraph::from_json(r#"["GG", {"id": "br", "branch": [{"id": "x", "seq": "ATCGAA"}, {"id": "y", "seq": "ATCTTA"}]}, "CC"]"#).unwrap();
+    let (g, map) = orig.normalize();
+    assert_eq!( seqs( &g ), seqs( &orig ) );
+    assert_eq!( g.to_json(), r#"["GGATC",{"branch":["GA","TT"],"id":"br"},"ACC"]"# );
+    assert_eq!( map["x"], vec![0, 2, 4] );
+    assert_eq!( map["br"], vec![1] );
+
+    // identical members collapse, and a branch left with one member is inlined
+    let orig = SeqGraph::from_json(r#"[{"branch": [{"id": "p", "seq": "AAT"}, "AAT"]}, {"branch": ["C", ["G", "T"], "C", "GT"]}]"#).unwrap();
+    let (g, map) = orig.normalize();
+    assert_eq!( seqs( &g ), vec![ "AATC", "AATGT" ] );
+    assert_eq!( g.to_json(), r#"[{"id":"p","seq":"AAT"},{"branch":["C","GT"]}]"# );
+    assert_eq!( map["p"], vec![0] );
+
+    // every member keeps at least one base
+    let orig = SeqGraph::from_json(r#"[{"branch": ["AT", "ATT"]}]"#).unwrap();
+    let (g, _) = orig.normalize();
+    assert_eq!( seqs( &g ), seqs( &orig ) );
+    assert_eq!( g.to_json(), r#"["A",{"branch":["T","TT"]}]"# );
+}
+
+
+#[test]
+fn test_repeat() {
+    let s = |x: &str| Sequence::from_str(x).unwrap();
+    let g = SeqGraph::from_json(r#"["ACGT", {"id": "str", "repeat": "CAG", "min": 2, "max": 10}, "TTGA"]"#).unwrap();
+    let id = g.node_id("str").unwrap();
+
+    let st = g.stats();
+    assert_eq!( (st.min_len, st.max_len, st.repeats, st.paths), (Some(14), Some(38), 1, 9) );
+    assert_eq!( g.paths().count(), 9 );
+    let three = Path { nodes: vec![0, id, 2], copies: vec![ (id, 3) ].into_iter().collect() };
+    assert_eq!( g.path_seq( &three ), s("ACGTCAGCAGCAGTTGA") );
+    assert_eq!( g.path_seq( &Path::from( vec![0, id, 2] ) ), s("ACGTCAGCAGTTGA") );
+
+    let read = |copies: usize| s( &format!("ACGT{}TTGA", "CAG".repeat(copies)) );
+    for &copies in [2, 5, 10].iter() {
+        let (path, r, q) = g.align__global_max( &read(copies), &params ).unwrap();
+        assert_eq!( g.repeat_copies( &path ), vec![ (id, copies) ] );
+        assert_eq!( (r, q), (read(copies), read(copies)) );
+
+        let (path, d) = g.align__edit_distance( &read(copies), &params ).unwrap();
+        assert_eq!( (g.repeat_copies( &path ), d), (vec![ (id, copies) ], 0) );
+    }
+
+    // beyond the allowed range, the nearest count is used and the rest is an indel
+    let (path, _, _) = g.align__global_max( &read(12), &params ).unwrap();
+    assert_eq!( g.repeat_copies( &path ), vec![ (id, 10) ] );
+
+    // a read starting after the repeat deletes only min copies of it
+    let affine = AlnParams { gap_open: -3, .. params };
+    let (path, r, q, score, _) = g.align__global_max_scored::<AlnScore>( &s("TTGA"), None, &affine ).unwrap();
+    assert_eq!( g.repeat_copies( &path ), vec![ (id, 2) ] );
+    assert_eq!( (r, q, score), (read(2), s("----------TTGA"), 4 - 3 - 9) );
+
+    // a read ending within the repeat counts the copies it reaches
+    let semi = AlnParams { mode: AlnMode::SemiGlobal, .. params };
+    let (path, _, _) = g.align__global_max( &s("GTCAGCAGCAGCA"), &semi ).unwrap();
+    assert_eq!( g.repeat_copies( &path ), vec![ (id, 4) ] );
+
+    let rec = sam::sam_record( &g, "r", &read(5), None, &path, &read(5), &read(5), sam::MAPQ_UNAVAILABLE, false, &params );
+    assert!( rec.ends_with("\tXR:Z:str=4") );
+    assert!( rec.contains("\tXP:Z:>0>str>2\t") );
+
+    assert_eq!( g.to_json(), r#"["ACGT",{"id":"str","max":10,"min":2,"repeat":"CAG"},"TTGA"]"# );
+    assert!( SeqGraph::from_json(r#"[{"repeat": "CAG", "min": 5, "max": 2}]"#).is_err() );
+    assert_eq!( SeqGraph::validate(r#"[{"repeat": "CAG", "min": 5, "max": 2}]"#)[0].severity, validate::Severity::Error );
+}
+
+#[test]
+fn test_optional() {
+    let s = |x: &str| Sequence::from_str(x).unwrap();
+    let g = SeqGraph::from_json(r#"["ACGTAC", {"id": "ins", "optional": "GGGG"}, "TTGACA",
+                                    {"id": "snp", "branch": ["A", ""]}, "CCTA"]"#).unwrap();
+    let (opt, snp) = (g.node_id("ins").unwrap(), g.node_id("snp").unwrap());
+    assert_eq!( (opt, snp, g.node_count()), (1, 5, 9) );
+    assert_eq!( g.stats().paths, 4 );
+    assert_eq!( g.paths().map( |h| h.name ).collect::<Vec<String>>(),
+                vec![ "0|2|4|6|8", "0|2|4|8", "0|4|6|8", "0|4|8" ] );
+
+    let (path, r, q) = g.align__global_max( &s("ACGTACGGGGTTGACACCTA"), &params ).unwrap();
+    assert_eq!( g.optionals( &path ), vec![ (opt, true) ] );
+    assert_eq!( (path.nodes, r.clone()), (vec![0, 1, 2, 4, 5, 7, 8], q) );
+
+    let (path, r, q) = g.align__global_max( &s("ACGTACTTGACAACCTA"), &params ).unwrap();
+    assert_eq!( g.optionals( &path ), vec![ (opt, false) ] );
+    assert_eq!( (path.nodes.clone(), r.clone()), (vec![0, 1, 3, 4, 5, 6, 8], q.clone()) );
+    assert_eq!( g.align__edit_distance( &q, &params ), Some((path.clone(), 0)) );
+
+    let rec = sam::sam_record( &g, "r", &q, None, &path, &r, &q, sam::MAPQ_UNAVAILABLE, false, &params );
+    assert!( rec.ends_with("\tXO:Z:ins=0") );
+
+    assert_eq!( g.to_json(), r#"["ACGTAC",{"id":"ins","optional":"GGGG"},"TTGACA",{"branch":["A",""],"id":"snp"},"CCTA"]"# );
+    let (n, _) = g.normalize();
+    assert_eq!( n.to_json(), g.to_json() );
+    assert!( SeqGraph::validate( &g.to_json() ).is_empty() );
+}
+
+#[test]
+fn test_weights() {
+    let s = |x: &str| Sequence::from_str(x).unwrap();
+    let json = r#"["ACGT", {"id": "snp", "branch": [{"id": "ref", "seq": "A"}, {"id": "alt", "seq": "C"}], "weights": [0.4, 0.6]}, "TTGA"]"#;
+    let g = SeqGraph::from_json( json ).unwrap();
+    let (alt, refr) = (g.node_id("alt").unwrap(), g.node_id("ref").unwrap());
+    let (snp, w) = match g.node( g.node_id("snp").unwrap() ) {
+        Some(&SeqNode::Branch { id, weights: ref w, .. }) => (id, w.clone()),
+        _ => panic!("expected a branch")
+    };
+    assert_eq!( (SeqGraph::prior_score( &w, 0, &params ), SeqGraph::prior_score( &w, 1, &params )), (-1, 0) );
+
+    // the read's evidence outweighs the prior...
+    let (path, _, _) = g.align__global_max( &s("ACGTATTGA"), &params ).unwrap();
+    assert_eq!( path.nodes, vec![0, snp, refr, 4] );
+    // ...but where it can't decide, the likelier member wins (unweighted, the first would)
+    let (path, _, _) = g.align__global_max( &s("ACGTGTTGA"), &params ).unwrap();
+    assert_eq!( path.nodes, vec![0, snp, alt, 4] );
+    let unweighted = SeqGraph::from_json( r#"["ACGT", {"branch": ["A", "C"]}, "TTGA"]"# ).unwrap();
+    assert_eq!( unweighted.align__global_max( &s("ACGTGTTGA"), &params ).unwrap().0.nodes, vec![0, 1, 2, 4] );
+
+    assert_eq!( SeqGraph::from_json( &g.to_json() ).unwrap().to_json(), g.to_json() );
+    assert!( g.to_json().contains(r#""weights":[0.4,0.6]"#) );
+    assert!( SeqGraph::from_json( r#"[{"branch": ["A", "C"], "weights": [1.0]}]"# ).is_err() );
+    assert!( SeqGraph::from_json( r#"[{"optional": "A", "weights": [1.0, 0.0]}]"# ).is_err() );
+    assert_eq!( SeqGraph::validate( r#"[{"branch": ["A", "C"], "weights": [1.0]}]"# ).len(), 1 );
+}
+
+#[test]
+fn test_posterior() {
+    let s = |x: &str| Sequence::from_str(x).unwrap();
+    let g = SeqGraph::from_json(r#"["ACGTAC", {"id": "snp", "branch": [{"id": "a", "seq": "A"}, {"id": "c", "seq": "C"}]},
+                                    "TTGACA", {"id": "ins", "optional": "GGG"}, "CCTA"]"#).unwrap();
+    let (snp, ins) = (g.node_id("snp").unwrap(), g.node_id("ins").unwrap());
+
+    // a score of `equal` is a likelihood ratio of 2, so gaps and mismatches must cost more
+    //   than the defaults for a read to say much
+    let pparams = AlnParams { equal: 2, mismatch: -6, gap_open: -8, gap_ext: -4, .. params };
+    let read = s("ACGTACATTGACAGGGCCTA");
+    let post = g.posteriors( &read, None, &pparams ).unwrap();
+    assert!( post[&snp][0] > 0.9 && post[&ins][0] > 0.99 );
+    for p in post.values() {
+        assert!( (p.iter().sum::<f64>() - 1.0).abs() < 1e-9 );
+    }
+    let (path, _, _) = g.align__global_max( &read, &params ).unwrap();
+    let taken = g.path_posteriors( &path, &post );
+    assert_eq!( taken.iter().map( |t| t.0 ).collect::<Vec<u32>>(), vec![ snp, ins ] );
+    assert_eq!( taken[0].1, post[&snp][0] );
+
+    // the read can't tell the members apart, so the posterior is (nearly) the prior
+    let ambiguous = s("ACGTACGTTGACACCTA");
+    let post = g.posteriors( &ambiguous, None, &pparams ).unwrap();
+    assert!( (post[&snp][0] - 0.5).abs() < 0.05 && post[&ins][1] > 0.99 );
+    let weighted = SeqGraph::from_json(r#"["ACGTAC", {"branch": ["A", "C"], "weights": [9, 1]}, "TTGACA"]"#).unwrap();
+    let post = weighted.posteriors( &s("ACGTACGTTGACA"), None, &pparams ).unwrap();
+    assert!( (post[&1][0] - 0.9).abs() < 0.05 );
+    // a nested branch's members sum to the member holding it
+    let nested = SeqGraph::from_json(r#"["ACGTAC", {"branch": [["A", {"id": "in", "branch": ["G", "T"]}], "C"]}, "TTGACA"]"#).unwrap();
+    let post = nested.posteriors( &s("ACGTACAGTTGACA"), None, &pparams ).unwrap();
+    let inner = &post[&nested.node_id("in").unwrap()];
+    assert!( post[&1][0] > 0.9 && inner[0] > 0.9 && (inner.iter().sum::<f64>() - post[&1][0]).abs() < 1e-9 );
+
+    assert!( g.posteriors( &read, None, &AlnParams { mode: AlnMode::Local, .. pparams } ).is_none() );
+    let semi = g.posteriors( &s("GTACATTGA"), None, &AlnParams { mode: AlnMode::SemiGlobal, .. pparams } ).unwrap();
+    // a branch the read doesn't reach is left at its prior
+    assert!( semi[&snp][0] > 0.9 && (semi[&ins][0] - 0.5).abs() < 1e-3 );
+}
+
+#[test]
+fn test_mapq() {
+    let s = |x: &str| Sequence::from_str(x).unwrap();
+    let g = SeqGraph::from_json(r#"["ACGTAC", {"id": "snp", "branch": ["A", "C"]}, "TTGACA"]"#).unwrap();
+
+    // the runner-up takes the other member: one mismatch, so 2 behind
+    let (_, _, _, score, runner_up) = g.align__global_max_scored::<AlnScore>( &s("ACGTACATTGACA"), None, &params ).unwrap();
+    assert_eq!( (score, runner_up), (13, Some(11)) );
+    assert_eq!( sam::mapq( score, runner_up, &params ), 7 );
+
+    // a tie, and no branch reached at all
+    let (_, _, _, score, runner_up) = g.align__global_max_scored::<AlnScore>( &s("ACGTACGTTGACA"), None, &params ).unwrap();
+    assert_eq!( (score, runner_up, sam::mapq( score, runner_up, &params )), (11, Some(11), 3) );
+    let semi = AlnParams { mode: AlnMode::SemiGlobal, .. params };
+    let (_, _, _, _, runner_up) = g.align__global_max_scored::<AlnScore>( &s("CGTA"), None, &semi ).unwrap();
+    assert_eq!( runner_up, None );
+    assert_eq!( sam::mapq( 4, None, &semi ), 60 );
+    assert_eq!( sam::mapq( 100, Some(0), &params ), 60 );
+}
+
+#[test]
+fn test_seeded() {
+    use index::*;
+    let s = |x: &str| Sequence::from_str(x).unwrap();
+
+    // banded alignment agrees with the full DP when the band holds the best alignment
+    let (r, q) = (s("ACGTTGCAAGTC"), s("ACGTGCAAGGTC"));
+    assert_eq!( align_banded::<AlnScore>( &r, &q, None, &params, (-1, 1) ).ok(), align( &r, &q, &params ) );
+    let semi = AlnParams { mode: AlnMode::SemiGlobal, .. params };
+    let (r, q) = (s("TTTTTACGTACGGG"), s("ACGTAC"));
+    assert_eq!( align_banded::<AlnScore>( &r, &q, None, &semi, (5, 5) ).ok(), align( &r, &q, &semi ) );
+
+    let g = SeqGraph::from_json(r#"["ACGTTGCA", {"id": "b", "branch": [{"id": "x", "seq": "GGATC"},
+                                                                       {"id": "y", "seq": "CCTAG"}]}, "TTGACAGT"]"#).unwrap();
+    let index = GraphIndex::new( &g, 4 );
+    // a k-mer spanning a fragment and a branch member
+    let y = g.node_id("y").unwrap();
+    assert!( index.lookup( &s("GCAC").0 ).iter().any( |seed| seed.id == 0 && seed.offset == 5 && seed.nodes.contains( &y ) ) );
+    assert!( index.lookup( &s("GCAT").0 ).is_empty() );
+
+    let q = s("ACGTTGCACCTTGTTGACAGT");
+    let seeded = g.align__seeded( &index, &q, None, &params, 3 ).unwrap();
+    assert_eq!( seeded, g.align__global_max( &q, &params ).unwrap() );
+    assert!( seeded.0.nodes.contains( &y ) );
+    assert_eq!( g.align__seeded( &index, &q, Some(&[40; 3]), &params, 3 ), Err(AlnErr::QualLength) );
+
+    // no seeds at all: aligned to every path instead
+    let q = s("ACCCCCCA");
+    assert!( index.seeds( &q ).is_empty() );
+    assert_eq!( g.align__seeded( &index, &q, None, &params, 3 ).unwrap(), g.align__global_max( &q, &params ).unwrap() );
+
+    // an empty member holds only the seeds passing straight through the branch, not those
+    //   through the other members
+    let g = SeqGraph::from_json(r#"["ACGTTGCA", {"id": "b", "branch": [[], {"id": "x", "seq": "GGATC"}]}, "TTGACAGT"]"#).unwrap();
+    let index = GraphIndex::new( &g, 4 );
+    let x = g.node_id("x").unwrap();
+    for q in [s("ACGTTGCAGGATCTTGACAGT"), s("ACGTTGCATTGACAGT")].iter() {
+        let seeded = g.align__seeded( &index, q, None, &params, 3 ).unwrap();
+        assert_eq!( seeded, g.align__global_max( q, &params ).unwrap() );
+        assert_eq!( seeded.0.nodes.contains( &x ), q.len() > 16 );
+    }
+}
+
+#[test]
+fn test_classify() {
+    let s = |x: &str| Sequence::from_str(x).unwrap();
+    let semi = AlnParams { mode: AlnMode::SemiGlobal, .. params };
+    let g = SeqGraph::from_json(r#"[{"id": "bc", "branch": [{"id": "bc1", "seq": "AAAACC"}, {"id": "bc2", "seq": "GGTTCA"}]},
+                                    "TTGACATG", {"id": "snp", "branch": ["A", "C"]}, {"id": "ins", "optional": "GG"}, "CCTAGGA"]"#).unwrap();
+    let id = |x: &str| g.node_id(x).unwrap();
+
+    let (path, r, q) = g.align__global_max( &s("GGTTCATTGACATGCCCTAG"), &semi ).unwrap();
+    let members = g.branch_members( &path );
+    assert_eq!( members[0], (id("bc"), id("bc2"), 0, 6) );
+    assert_eq!( (members[1].2, members[1].3), (14, 15) );
+    // the skipped optional is empty, between the SNP and CCTAGGA
+    assert_eq!( (members[2].2, members[2].3), (15, 15) );
+    let assigned = g.classify( &path, &r, &q );
+    assert_eq!( assigned[0], (id("bc"), Some(id("bc2"))) );
+    assert_eq!( assigned[1], (id("snp"), Some(id("snp") + 2)) );
+    assert_eq!( assigned[2].1, Some(members[2].1) );
+
+    // a read ending before the SNP isn't assigned there, nor to the optional
+    let (path, r, q) = g.align__global_max( &s("AAAACCTTGAC"), &semi ).unwrap();
+    let assigned = g.classify( &path, &r, &q );
+    assert_eq!( assigned[0], (id("bc"), Some(id("bc1"))) );
+    assert_eq!( (assigned[1].1, assigned[2].1), (None, None) );
+}
+
+#[test]
+fn test_capture() {
+    let s = |x: &str| Sequence::from_str(x).unwrap();
+    let semi = AlnParams { mode: AlnMode::SemiGlobal, .. params };
+
+    // N matches any base
+    assert!( bases_match( WILDCARD, 2 ) && !bases_match( WILDCARD, HYPHEN ) );
+    assert_eq!( format!("{}", s("ANNT")), "ANNT" );
+    assert_eq!( myers::edit_distance( &s("ACNNTG"), &s("ACGATG"), false, false ), 0 );
+
+    let g = SeqGraph::from_json(r#"["ACGTACGGTC", {"id": "umi", "seq": "NNNNNN", "capture": true}, "TTGACATG",
+                                    {"id": "bc", "seq": "NNNN", "capture": true}, "CCTAGGA"]"#).unwrap();
+    assert_eq!( SeqGraph::from_json( &g.to_json() ).unwrap().to_json(), g.to_json() );
+    assert_eq!( g.normalize().0.to_json(), g.to_json() );
+
+    // an inserted base within the UMI is captured
+    let q = s("ACGTACGGTCAGGCCTTTTGACATGGATCCCTAGGA");
+    let quals : Vec<u8> = (0 .. q.len() as u8).collect();
+    let (path, r, pq) = g.align__global_max( &q, &semi ).unwrap();
+    assert_eq!( score_padded( &r, &pq, &semi ), 34 );
+    let caps = g.captures( &path, &r, &pq, Some(&quals) ).unwrap();
+    assert_eq!( caps[0], (g.node_id("umi").unwrap(), s("AGGCCTT"), Some(vec![10, 11, 12, 13, 14, 15, 16])) );
+    assert_eq!( caps[1], (g.node_id("bc").unwrap(), s("GATC"), Some(vec![25, 26, 27, 28])) );
+
+    // a read ending before the barcode captures none of it
+    let q = s("CGGTCAGGCTTTTGA");
+    let (path, r, pq) = g.align__global_max( &q, &semi ).unwrap();
+    let caps = g.captures( &path, &r, &pq, None ).unwrap();
+    assert_eq!( (caps[0].1.clone(), caps[1].1.len(), caps[1].2.clone()), (s("AGGCTT"), 0, None) );
+}
+
+#[test]
+fn test_consensus() {
+    use consensus::*;
+    let s = |x: &str| Sequence::from_str(x).unwrap();
+    let semi = AlnParams { mode: AlnMode::SemiGlobal, .. params };
+    let g = SeqGraph::from_json(r#"["ACGTACGGTC", {"id": "snp", "branch": ["A", "C"]}, {"id": "amp", "seq": "TTGACATGCCTAGGA"}]"#).unwrap();
+
+    // two reads of five have T for A, and two insert Gs; one of those starts late
+    let mut pileup = Pileup::new();
+    for q in ["ACGTACGGTCATTGACTTGCCTAGGA", "ACGTACGGTCATTGACTTGCCTAGGA", "ACGTACGGTCATTGACATGCCTAGGA",
+              "GTACGGTCATTGAGACATGGCCTAG", "ACGTACGGTCATTGAGACATGGCCTAGGA"].iter() {
+        let (path, r, pq) = g.align__global_max( &s(q), &semi ).unwrap();
+        pileup.add( &g, &path, &r, &pq );
+    }
+    let calls = pileup.consensus( &g );
+    assert_eq!( calls.iter().map( |c| c.id ).collect::<Vec<u32>>(), vec![ 0, 2, 4 ] );
+    assert_eq!( calls[0].seq, s("ACGTACGGTC") );
+    assert_eq!( &calls[0].depth[.. 3], &[4, 4, 5] );
+
+    let amp = &calls[2];
+    assert_eq!( amp.depth.len(), 15 );
+    assert_eq!( amp.depth[14], 4 );
+    // neither the T nor the insertions are in a majority of the reads
+    assert_eq!( amp.seq, s("TTGACATGCCTAGGA") );
+    assert!( (amp.discordance[5] - 0.4).abs() < 1e-9 );
+    assert_eq!( amp.discordance[0], 0. );
 }
@@ -0,0 +1,141 @@
+use std::cmp::min;
+use seq::*;
+
+
+const WORD : usize = 64;
+const HIGH_BIT : u64 = 1 << (WORD - 1);
+
+/// Peq: Myers' pattern-match bit-vectors for the query, one set of blocks per base
+///
+/// bit `i % 64` of block `i / 64` in `Peq.0[c]` is set iff `query[i]` matches c (see
+/// bases_match): a wildcard in the query sets its bit for every base, and `Peq.0[WILDCARD]`
+/// is set for every query base
+///
+pub struct Peq( pub Vec<Vec<u64>>, pub usize );
+
+impl Peq {
+    pub fn new( query: &Sequence ) -> Peq {
+        let blocks = (query.len() + WORD - 1) / WORD;
+        let mut eq = vec![ vec![0u64; blocks]; (WILDCARD + 1) as usize ];
+        for (i, &c) in query.0.iter().enumerate() {
+            for r in 0 .. eq.len() as Mmer {
+                if bases_match( c, r ) {
+                    eq[ r as usize ][ i / WORD ] |= 1u64 << (i % WORD);
+                }
+            }
+        }
+        Peq( eq, query.len() )
+    }
+
+    pub fn len(&self) -> usize { self.1 }
+    pub fn blocks(&self) -> usize { (self.1 + WORD - 1) / WORD }
+}
+
+/// BitState: one DP column in Myers' bit-vector encoding
+///
+/// `pv`/`mv` hold the +1/-1 vertical deltas, `score[b]` is the DP value in the last row of
+/// block `b`, and `best` is the minimum seen so far in the query's last row (used when the
+/// reference end is free, ie. `rlocal`)
+///
+#[derive(Clone, Debug)]
+pub struct BitState {
+    pv: Vec<u64>,
+    mv: Vec<u64>,
+    score: Vec<i64>,
+    pub best: i64,
+    llocal: bool,
+    row0: i64,
+}
+
+impl BitState {
+    /// BitState::new -- the first column, D[i][0] = i
+    pub fn new( peq: &Peq, llocal: bool ) -> BitState {
+        let blocks = peq.blocks();
+        let mut st = BitState {
+            pv:    vec![ !0u64; blocks ],
+            mv:    vec![ 0u64; blocks ],
+            score: (0 .. blocks).map( |b| ((b + 1) * WORD) as i64 ).collect(),
+            best:  i64::max_value(),
+            llocal: llocal,
+            row0:  0,
+        };
+        st.best = st.last_row( peq );
+        st
+    }
+
+    /// BitState::last_row -- DP value in the query's last row for the current column
+    pub fn last_row(&self, peq: &Peq) -> i64 {
+        if peq.len() == 0 {
+            return self.row0;
+        }
+        let b = self.score.len() - 1;
+        let used = peq.len() - b * WORD;
+        if used == WORD {
+            self.score[b]
+        } else {
+            // rows past the end of the query are padding; back their deltas out
+            let above = !0u64 << used;
+            self.score[b] - (self.pv[b] & above).count_ones() as i64 + (self.mv[b] & above).count_ones() as i64
+        }
+    }
+
+    /// BitState::advance -- consume one reference base, producing the next column
+    pub fn advance(&mut self, peq: &Peq, base: Mmer) {
+        if !self.llocal {
+            self.row0 += 1;
+        }
+        if peq.len() == 0 {
+            self.best = min( self.best, self.row0 );
+            return;
+        }
+        // with a global left edge, D[0][j] = j, so every column adds 1 in row 0
+        let mut hin : i64 = if self.llocal { 0 } else { 1 };
+        let empty = Vec::new();
+        let eqs = if (base as usize) < peq.0.len() { &peq.0[ base as usize ] } else { &empty };
+
+        for b in 0 .. self.pv.len() {
+            let pv = self.pv[b];
+            let mv = self.mv[b];
+            let mut eq = if eqs.is_empty() { 0 } else { eqs[b] };
+
+            let xv = eq | mv;
+            if hin < 0 { eq |= 1; }
+            let xh = ((eq & pv).wrapping_add(pv) ^ pv) | eq;
+            let mut ph = mv | !(xh | pv);
+            let mut mh = pv & xh;
+
+            let hout : i64 = if ph & HIGH_BIT != 0 { 1 } else if mh & HIGH_BIT != 0 { -1 } else { 0 };
+
+            ph <<= 1;
+            mh <<= 1;
+            if hin < 0 { mh |= 1; } else if hin > 0 { ph |= 1; }
+
+            self.pv[b] = mh | !(xv | ph);
+            self.mv[b] = ph & xv;
+            self.score[b] += hout;
+            hin = hout;
+        }
+        self.best = min( self.best, self.last_row( peq ) );
+    }
+
+    /// BitState::distance -- edit distance if the reference were to end here
+    pub fn distance(&self, peq: &Peq, rlocal: bool) -> i64 {
+        if rlocal { self.best } else { self.last_row( peq ) }
+    }
+}
+
+/// edit_distance -- unit-cost (Levenshtein) distance between query and reference
+///
+/// with `llocal` set, the reference may be entered late at no cost, and with `rlocal` set,
+/// left early; both set gives the best placement of the query anywhere within the
+/// reference (as AlnMode::SemiGlobal), and neither the distance between the whole
+/// sequences (as AlnMode::Global)
+///
+pub fn edit_distance( reference: &Sequence, query: &Sequence, llocal: bool, rlocal: bool ) -> usize {
+    let peq = Peq::new( query );
+    let mut st = BitState::new( &peq, llocal );
+    for &c in reference.0.iter() {
+        st.advance( &peq, c );
+    }
+    st.distance( &peq, rlocal ) as usize
+}
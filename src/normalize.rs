@@ -0,0 +1,313 @@
+extern crate serde_json;
+
+use std::cmp::min;
+use std::collections::BTreeMap;
+use self::serde_json::Value as JSON_Val;
+use seq::*;
+use tree::*;
+
+
+// a graph as chains of elements, while it's rearranged.  `names` are the old names whose
+//   sequence this element (partly) holds.  A `capture` fragment is kept whole, as its
+//   bases are reported on their own
+#[derive(Clone)]
+enum Elem {
+    Frag { names: Vec<String>, seq: Vec<Mmer>, capture: bool },
+    Repeat { names: Vec<String>, unit: Vec<Mmer>, min: usize, max: usize },
+    // an optional node is kept as a branch whose second member is empty
+    Branch { names: Vec<String>, members: Vec<Vec<Elem>>, optional: bool, weights: Option<Vec<f64>> }
+}
+
+fn chain_of( graph: &SeqGraph, node: &SeqNode, stop: *const SeqNode ) -> Vec<Elem> {
+    let mut chain = Vec::new();
+    let mut node = node;
+    while node as *const SeqNode != stop {
+        let names = |id: u32| graph.name(id).map( |n| vec![ n.to_string() ] ).unwrap_or( Vec::new() );
+        node = match node {
+            &SeqNode::Nil => break,
+            &SeqNode::Frag { id, val: ref val, capture, next: ref next, ..} => {
+                chain.push( Elem::Frag { names: names(id), seq: val.0.clone(), capture: capture } );
+                next
+            },
+            &SeqNode::Repeat { id, unit: ref unit, min: lo, max: hi, next: ref next } => {
+                chain.push( Elem::Repeat { names: names(id), unit: unit.0.clone(), min: lo, max: hi } );
+                next
+            },
+            &SeqNode::Branch { id, optional, weights: ref weights, members: ref members, next: ref next, ..} => {
+                let m = members.iter().map( |n| chain_of( graph, n, &**next ) ).collect();
+                chain.push( Elem::Branch { names: names(id), members: m, optional: optional, weights: weights.clone() } );
+                next
+            }
+        };
+    }
+    chain
+}
+
+// same sequence and shape, whatever the names
+fn same( a: &[Elem], b: &[Elem] ) -> bool {
+    a.len() == b.len() && a.iter().zip( b.iter() ).all( |pair| match pair {
+        (&Elem::Frag { seq: ref x, capture: a, .. }, &Elem::Frag { seq: ref y, capture: b, .. }) => x == y && a == b,
+        (&Elem::Repeat { unit: ref x, min: a, max: b, .. }, &Elem::Repeat { unit: ref y, min: c, max: d, .. }) =>
+            x == y && a == c && b == d,
+        (&Elem::Branch { members: ref x, weights: ref v, .. }, &Elem::Branch { members: ref y, weights: ref w, .. }) =>
+            x.len() == y.len() && v == w && x.iter().zip( y.iter() ).all( |(p, q)| same( p, q ) ),
+        _ => false
+    })
+}
+
+// add the names of `from` to the corresponding elements of `into` (which is `same`)
+fn merge_names( into: &mut [Elem], from: &[Elem] ) {
+    for pair in into.iter_mut().zip( from.iter() ) {
+        match pair {
+            (&mut Elem::Frag { names: ref mut n, .. }, &Elem::Frag { names: ref m, .. })
+                | (&mut Elem::Repeat { names: ref mut n, .. }, &Elem::Repeat { names: ref m, .. }) => n.extend( m.iter().cloned() ),
+            (&mut Elem::Branch { names: ref mut n, members: ref mut x, .. }, &Elem::Branch { names: ref m, members: ref y, .. }) => {
+                n.extend( m.iter().cloned() );
+                for (p, q) in x.iter_mut().zip( y.iter() ) {
+                    merge_names( p, q );
+                }
+            },
+            _ => ()
+        }
+    }
+}
+
+// append an element, merging it into a preceding fragment and dropping empty fragments
+//   (captures aside)
+fn push( chain: &mut Vec<Elem>, elem: Elem ) {
+    if let Elem::Frag { names: ref names, seq: ref seq, capture: false } = elem {
+        if seq.is_empty() {
+            return;
+        }
+        if let Some(&mut Elem::Frag { names: ref mut last_names, seq: ref mut last_seq, capture: false }) = chain.last_mut() {
+            last_seq.extend( seq.iter().cloned() );
+            for n in names {
+                if !last_names.contains(n) {
+                    last_names.push( n.clone() );
+                }
+            }
+            return;
+        }
+    }
+    chain.push( elem );
+}
+
+// bases that may be hoisted from a member's first (or last) fragment, keeping at least one
+//   base in every member
+fn hoistable( member: &[Elem], front: bool ) -> Option<&[Mmer]> {
+    let end = if front { member.first() } else { member.last() };
+    match end {
+        Some(&Elem::Frag { seq: ref seq, capture: false, .. }) => {
+            let keep = if member.len() == 1 { 1 } else { 0 };
+            let n = seq.len().saturating_sub( keep );
+            Some( if front { &seq[.. n] } else { &seq[seq.len() - n ..] } )
+        },
+        _ => None
+    }
+}
+
+// take `n` bases from the front (or back) of each member, as a fragment named after the
+//   fragments they came from
+fn hoist( members: &mut Vec<Vec<Elem>>, n: usize, front: bool ) -> Elem {
+    let mut hoisted = Elem::Frag { names: Vec::new(), seq: Vec::new(), capture: false };
+    for m in members.iter_mut() {
+        let pos = if front { 0 } else { m.len() - 1 };
+        let emptied = match m[pos] {
+            Elem::Frag { names: ref names, seq: ref mut seq, .. } => {
+                let taken : Vec<Mmer> = if front { seq.drain( .. n ).collect() } else { let k = seq.len() - n; seq.drain( k .. ).collect() };
+                if let Elem::Frag { names: ref mut h_names, seq: ref mut h_seq, .. } = hoisted {
+                    *h_seq = taken;
+                    for name in names {
+                        if !h_names.contains(name) {
+                            h_names.push( name.clone() );
+                        }
+                    }
+                }
+                seq.is_empty()
+            },
+            _ => false
+        };
+        if emptied {
+            m.remove( pos );
+        }
+    }
+    hoisted
+}
+
+fn normalize_chain( chain: Vec<Elem> ) -> Vec<Elem> {
+    let mut out = Vec::new();
+    for elem in chain {
+        match elem {
+            Elem::Frag { .. } | Elem::Repeat { .. } => push( &mut out, elem ),
+            Elem::Branch { names, members, optional, weights } => {
+                // collapse identical members, adding their weights
+                let mut uniq : Vec<Vec<Elem>> = Vec::new();
+                let mut uniq_w : Vec<f64> = Vec::new();
+                for (i, m) in members.into_iter().map( normalize_chain ).enumerate() {
+                    let w = weights.as_ref().map( |w| w[i] ).unwrap_or(0.0);
+                    match uniq.iter().position( |u| same( u, &m ) ) {
+                        Some(k) => { merge_names( &mut uniq[k], &m ); uniq_w[k] += w },
+                        None => { uniq.push( m ); uniq_w.push( w ) }
+                    }
+                }
+                let weights = weights.map( |_| uniq_w );
+
+                if uniq.len() == 1 {
+                    // a branch with one member is just that member
+                    let mut inner = uniq.pop().unwrap();
+                    merge_names_all( &mut inner, &names );
+                    for e in inner {
+                        push( &mut out, e );
+                    }
+                    continue;
+                }
+
+                let common = |members: &Vec<Vec<Elem>>, front: bool| -> usize {
+                    let ends : Vec<Option<&[Mmer]>> = members.iter().map( |m| hoistable( m, front ) ).collect();
+                    if members.len() < 2 || ends.iter().any( |e| e.is_none() ) {
+                        return 0;
+                    }
+                    let first = ends[0].unwrap();
+                    ends.iter().map( |e| {
+                        let e = e.unwrap();
+                        let k = min( first.len(), e.len() );
+                        if front {
+                            (0 .. k).take_while( |&i| first[i] == e[i] ).count()
+                        } else {
+                            (0 .. k).take_while( |&i| first[first.len() - 1 - i] == e[e.len() - 1 - i] ).count()
+                        }
+                    }).min().unwrap_or(0)
+                };
+
+                let mut uniq = uniq;
+                let n_pre = common( &uniq, true );
+                if n_pre > 0 {
+                    let prefix = hoist( &mut uniq, n_pre, true );
+                    push( &mut out, prefix );
+                }
+                let n_suf = common( &uniq, false );
+                let suffix = if n_suf > 0 { Some( hoist( &mut uniq, n_suf, false ) ) } else { None };
+
+                let optional = optional && uniq.len() == 2 && uniq[1].is_empty();
+                out.push( Elem::Branch { names: names, members: uniq, optional: optional, weights: weights } );
+                if let Some(s) = suffix {
+                    push( &mut out, s );
+                }
+            }
+        }
+    }
+    out
+}
+
+// add `names` to every element of a chain
+fn merge_names_all( chain: &mut [Elem], names: &[String] ) {
+    for e in chain.iter_mut() {
+        match e {
+            &mut Elem::Frag { names: ref mut n, .. } | &mut Elem::Repeat { names: ref mut n, .. }
+                | &mut Elem::Branch { names: ref mut n, .. } => {
+                for name in names {
+                    if !n.contains(name) {
+                        n.push( name.clone() );
+                    }
+                }
+            }
+        }
+    }
+}
+
+// graph-JSON for a chain, recording the (pre-order) id each old name ends up on
+fn to_json( chain: &[Elem], idx: &mut u32, map: &mut BTreeMap<String, Vec<u32>> ) -> Vec<JSON_Val> {
+    chain.iter().map( |e| {
+        let id = *idx;
+        *idx += 1;
+        let (names, key, val) = match e {
+            &Elem::Frag { names: ref names, seq: ref seq, .. } =>
+                (names, "seq", JSON_Val::String( Sequence::mmer_to_str( seq ) )),
+            &Elem::Repeat { names: ref names, unit: ref unit, .. } =>
+                (names, "repeat", JSON_Val::String( Sequence::mmer_to_str( unit ) )),
+            &Elem::Branch { names: ref names, members: ref members, optional: true, .. } => {
+                let mut c = to_json( &members[0], idx, map );
+                // the skip fragment
+                *idx += 1;
+                (names, "optional", if c.len() == 1 { c.pop().unwrap() } else { JSON_Val::Array(c) })
+            },
+            &Elem::Branch { names: ref names, members: ref members, .. } =>
+                (names, "branch", JSON_Val::Array( members.iter().map( |m| {
+                    let mut c = to_json( m, idx, map );
+                    // an empty member (eg. a deletion) is kept as a zero-length fragment
+                    if c.is_empty() {
+                        *idx += 1;
+                        JSON_Val::String( String::new() )
+                    } else if c.len() == 1 { c.pop().unwrap() } else { JSON_Val::Array(c) }
+                }).collect() ))
+        };
+        for n in names {
+            map.entry( n.clone() ).or_insert( Vec::new() ).push( id );
+        }
+        let mut obj = BTreeMap::new();
+        obj.insert( key.to_string(), val );
+        if let &Elem::Repeat { min: lo, max: hi, .. } = e {
+            obj.insert( "min".to_string(), JSON_Val::U64( lo as u64 ) );
+            obj.insert( "max".to_string(), JSON_Val::U64( hi as u64 ) );
+        }
+        if let &Elem::Frag { capture: true, .. } = e {
+            obj.insert( "capture".to_string(), JSON_Val::Bool(true) );
+        }
+        if let &Elem::Branch { weights: Some(ref w), .. } = e {
+            obj.insert( "weights".to_string(), JSON_Val::Array( w.iter().map( |&x| JSON_Val::F64(x) ).collect() ) );
+        }
+        // a node keeps its first name (see strip, below)
+        if let Some(first) = names.first() {
+            obj.insert( "id".to_string(), JSON_Val::String( first.clone() ) );
+        }
+        JSON_Val::Object(obj)
+    }).collect()
+}
+
+
+impl SeqGraph {
+    /// SeqGraph::normalize -- an equivalent graph (spelling the same set of haplotypes) with
+    ///   adjacent fragments merged, identical branch members collapsed (adding their
+    ///   weights), single-member branches inlined, and sequence shared by the start (or end)
+    ///   of every member of a branch hoisted out in front of (or behind) it.  Every member
+    ///   keeps at least one node, and capture fragments are kept whole.
+    ///
+    /// Also returned is a map from each old name to the new nodes holding its sequence: one
+    ///   name can be spread over several nodes (eg. a hoisted prefix), and a node can hold
+    ///   several names (eg. merged fragments); a new node keeps the first of its names.
+    pub fn normalize(&self) -> (SeqGraph, BTreeMap<String, Vec<u32>>) {
+        let chain = normalize_chain( chain_of( self, self.root(), 0 as *const SeqNode ) );
+        let mut map = BTreeMap::new();
+        let mut idx = 0;
+        let mut value = to_json( &chain, &mut idx, &mut map );
+
+        // drop "id"s of names now spread over several nodes, so node_id() stays unambiguous
+        fn strip( v: &mut JSON_Val, map: &BTreeMap<String, Vec<u32>> ) {
+            match v {
+                &mut JSON_Val::Array(ref mut l) => for x in l.iter_mut() { strip( x, map ) },
+                &mut JSON_Val::Object(ref mut obj) => {
+                    let split = match obj.get("id") {
+                        Some(&JSON_Val::String(ref n)) => map.get(n).map( |ids| ids.len() > 1 ).unwrap_or(false),
+                        _ => false
+                    };
+                    if split {
+                        obj.remove("id");
+                    }
+                    for key in ["branch", "optional"].iter() {
+                        if let Some(b) = obj.get_mut(*key) {
+                            strip( b, map );
+                        }
+                    }
+                },
+                _ => ()
+            }
+        }
+        for v in value.iter_mut() {
+            strip( v, &map );
+        }
+
+        let graph = SeqGraph::from_json( &serde_json::to_string( &JSON_Val::Array(value) ).unwrap() )
+            .expect("normalized graph should parse");
+        (graph, map)
+    }
+}
@@ -0,0 +1,370 @@
+use std::collections::BTreeMap;
+use std::f64::NEG_INFINITY;
+use seq::*;
+use align::*;
+use tree::*;
+
+
+// log(e^a + e^b), without leaving log space
+fn log_add( a: f64, b: f64 ) -> f64 {
+    if a == NEG_INFINITY {
+        return b;
+    }
+    if b == NEG_INFINITY {
+        return a;
+    }
+    let hi = a.max(b);
+    hi + ((a - hi).exp() + (b - hi).exp()).ln()
+}
+
+// forward values at one reference column, per query prefix length: the log-likelihood
+//   summed over alignments reaching that cell in a match (or the start), deletion or insertion.
+//   Where the reference's ends are free, `s` holds the weight of alignments not yet started
+//   and `e` that of those already ended, so that they're weighed by the path they're on,
+//   like any other
+#[derive(Clone)]
+struct Column {
+    m: Vec<f64>,
+    d: Vec<f64>,
+    i: Vec<f64>,
+    s: f64,
+    e: f64
+}
+
+impl Column {
+    fn empty( query_len: usize ) -> Column {
+        Column { m: vec![ NEG_INFINITY; query_len + 1 ], d: vec![ NEG_INFINITY; query_len + 1 ],
+                 i: vec![ NEG_INFINITY; query_len + 1 ], s: NEG_INFINITY, e: NEG_INFINITY }
+    }
+
+    fn total( &self, j: usize ) -> f64 {
+        log_add( log_add( self.m[j], self.d[j] ), self.i[j] )
+    }
+
+    // add in another column's paths, weighted by e^w
+    fn add( &mut self, other: &Column, w: f64 ) {
+        for j in 0 .. self.m.len() {
+            self.m[j] = log_add( self.m[j], other.m[j] + w );
+            self.d[j] = log_add( self.d[j], other.d[j] + w );
+            self.i[j] = log_add( self.i[j], other.i[j] + w );
+        }
+        self.s = log_add( self.s, other.s + w );
+        self.e = log_add( self.e, other.e + w );
+    }
+
+    // sum over cells of forward (self) times backward (other) values: the log-likelihood of
+    //   the alignments passing through this column
+    fn dot( &self, other: &Column ) -> f64 {
+        let mut z = log_add( self.s + other.s, self.e + other.e );
+        for j in 0 .. self.m.len() {
+            z = log_add( z, self.m[j] + other.m[j] );
+            z = log_add( z, self.d[j] + other.d[j] );
+            z = log_add( z, self.i[j] + other.i[j] );
+        }
+        z
+    }
+}
+
+// the forward-backward algorithm over a graph: scores become log-likelihoods by taking a
+//   score of `equal` as a likelihood ratio of 2, as with branch priors (see
+//   SeqGraph::prior_score).  Backward values are those of the rest of the alignment from a
+//   cell on, so that forward times backward, summed over a column, is the likelihood of
+//   the alignments passing through it
+struct Forward<'a> {
+    query: &'a Sequence,
+    // (match, mismatch) per query position
+    scores: Vec<(f64, f64)>,
+    open: f64,
+    ext: f64,
+    mode: AlnMode
+}
+
+impl<'a> Forward<'a> {
+    fn start( &self ) -> Column {
+        let n = self.query.len();
+        let mut c = Column::empty( n );
+        c.m[0] = 0.;
+        if self.mode.ref_ends_free() {
+            c.s = 0.;
+        }
+        for j in 1 .. n + 1 {
+            if self.mode.query_ends_free() {
+                c.m[j] = 0.;
+            } else {
+                c.i[j] = log_add( c.m[j-1] + self.open, c.i[j-1] + self.ext );
+            }
+        }
+        c
+    }
+
+    // the column after one more reference base
+    fn advance( &self, prev: &Column, base: Mmer ) -> Column {
+        let n = self.query.len();
+        let mut c = Column::empty( n );
+        c.s = prev.s;
+        c.e = prev.e;
+        if self.mode.ref_ends_free() {
+            c.m[0] = prev.s;
+        } else {
+            c.d[0] = log_add( log_add( prev.m[0], prev.i[0] ) + self.open, prev.d[0] + self.ext );
+        }
+        for j in 1 .. n + 1 {
+            let (eq, mm) = self.scores[j-1];
+            c.m[j] = prev.total( j-1 ) + if bases_match( base, self.query.0[j-1] ) { eq } else { mm };
+            c.d[j] = log_add( log_add( prev.m[j], prev.i[j] ) + self.open, prev.d[j] + self.ext );
+            c.i[j] = log_add( log_add( c.m[j-1], c.d[j-1] ) + self.open, c.i[j-1] + self.ext );
+        }
+        if self.mode.ref_ends_free() {
+            c.e = log_add( c.e, c.total( n ) );
+        }
+        c
+    }
+
+    // the backward values at the column before one more reference base, given those after
+    //   it: advance, transposed
+    fn retreat( &self, next: &Column, base: Mmer ) -> Column {
+        let n = self.query.len();
+        let free = self.mode.ref_ends_free();
+
+        // first within the later column: its last row feeds the ended alignments, and each
+        //   row the insertions below it
+        let mut g = next.clone();
+        if free {
+            g.m[n] = log_add( g.m[n], next.e );
+            g.d[n] = log_add( g.d[n], next.e );
+            g.i[n] = log_add( g.i[n], next.e );
+        }
+        for j in (1 .. n + 1).rev() {
+            let gi = g.i[j];
+            g.m[j-1] = log_add( g.m[j-1], gi + self.open );
+            g.d[j-1] = log_add( g.d[j-1], gi + self.open );
+            g.i[j-1] = log_add( g.i[j-1], gi + self.ext );
+        }
+
+        let mut c = Column::empty( n );
+        c.s = next.s;
+        c.e = next.e;
+        if free {
+            c.s = log_add( c.s, g.m[0] );
+        } else {
+            c.m[0] = g.d[0] + self.open;
+            c.i[0] = g.d[0] + self.open;
+            c.d[0] = g.d[0] + self.ext;
+        }
+        for j in 1 .. n + 1 {
+            let (eq, mm) = self.scores[j-1];
+            let gm = g.m[j] + if bases_match( base, self.query.0[j-1] ) { eq } else { mm };
+            c.m[j-1] = log_add( c.m[j-1], gm );
+            c.d[j-1] = log_add( c.d[j-1], gm );
+            c.i[j-1] = log_add( c.i[j-1], gm );
+            c.m[j] = log_add( c.m[j], g.d[j] + self.open );
+            c.i[j] = log_add( c.i[j], g.d[j] + self.open );
+            c.d[j] = log_add( c.d[j], g.d[j] + self.ext );
+        }
+        c
+    }
+
+    // the backward values past the last column, as the mode allows the alignment to end
+    fn end( &self ) -> Column {
+        let n = self.query.len();
+        let mut c = Column::empty( n );
+        if self.mode.ref_ends_free() {
+            c.e = 0.;
+        } else {
+            c.m[n] = 0.;
+            c.d[n] = 0.;
+            c.i[n] = 0.;
+        }
+        if self.mode.query_ends_free() {
+            for j in 0 .. n {
+                c.m[j] = 0.;
+                c.d[j] = 0.;
+                c.i[j] = 0.;
+            }
+        }
+        c
+    }
+
+    // the forward column after the nodes from `node` up to `stop`, recording the column each
+    //   member of a branch ends in, weighted by its prior
+    fn walk( &self, node: &SeqNode, stop: *const SeqNode, col: Column, exits: &mut BTreeMap<u32, Vec<Column>> )
+             -> Column {
+        let n = self.query.len();
+        let mut col = col;
+        let mut node = node;
+        while node as *const SeqNode != stop {
+            node = match node {
+                &SeqNode::Nil => break,
+                &SeqNode::Frag { val: ref val, next: ref next, ..} => {
+                    for &b in val.0.iter() {
+                        col = self.advance( &col, b );
+                    }
+                    next
+                },
+                &SeqNode::Repeat { unit: ref unit, min: lo, max: hi, next: ref next, ..} => {
+                    // copy counts are equally likely
+                    let counts = (hi - lo + 1) as f64;
+                    let mut out = Column::empty( n );
+                    for k in 0 .. hi + 1 {
+                        if k >= lo {
+                            out.add( &col, -counts.ln() );
+                        }
+                        if k < hi {
+                            for &b in unit.0.iter() {
+                                col = self.advance( &col, b );
+                            }
+                        }
+                    }
+                    col = out;
+                    next
+                },
+                &SeqNode::Branch { id, weights: ref weights, members: ref members, next: ref next, ..} => {
+                    let total : f64 = match weights {
+                        &Some(ref w) => w.iter().sum(),
+                        &None => members.len() as f64
+                    };
+                    let mut out = Column::empty( n );
+                    let mut ends = Vec::with_capacity( members.len() );
+                    for (k, member) in members.iter().enumerate() {
+                        let prior = (weights.as_ref().map( |w| w[k] ).unwrap_or(1.) / total).ln();
+                        // the prior is taken on entering the member, so that the columns
+                        //   recorded within it are weighted by it too
+                        let mut entry = Column::empty( n );
+                        entry.add( &col, prior );
+                        let c = self.walk( member, &**next, entry, exits );
+                        out.add( &c, 0. );
+                        ends.push( c );
+                    }
+                    exits.insert( id, ends );
+                    col = out;
+                    next
+                }
+            };
+        }
+        col
+    }
+
+    // the backward values at the start of `node`, given those at `stop`, recording those
+    //   at the end of each branch
+    fn back( &self, node: &SeqNode, stop: *const SeqNode, beta: Column, after: &mut BTreeMap<u32, Column> )
+             -> Column {
+        let n = self.query.len();
+        let mut chain = Vec::new();
+        let mut node = node;
+        while node as *const SeqNode != stop {
+            node = match node {
+                &SeqNode::Nil => break,
+                &SeqNode::Frag { next: ref next, ..} | &SeqNode::Repeat { next: ref next, ..}
+                    | &SeqNode::Branch { next: ref next, ..} => {
+                    chain.push( node );
+                    next
+                }
+            };
+        }
+
+        let mut beta = beta;
+        for node in chain.into_iter().rev() {
+            beta = match node {
+                &SeqNode::Frag { val: ref val, ..} => {
+                    for &b in val.0.iter().rev() {
+                        beta = self.retreat( &beta, b );
+                    }
+                    beta
+                },
+                // the forward sum over counts, transposed: after k copies, the values of
+                //   leaving there plus those of another copy
+                &SeqNode::Repeat { unit: ref unit, min: lo, max: hi, ..} => {
+                    let counts = (hi - lo + 1) as f64;
+                    let mut c = Column::empty( n );
+                    for k in (0 .. hi + 1).rev() {
+                        if k < hi {
+                            for &b in unit.0.iter().rev() {
+                                c = self.retreat( &c, b );
+                            }
+                        }
+                        if k >= lo {
+                            c.add( &beta, -counts.ln() );
+                        }
+                    }
+                    c
+                },
+                &SeqNode::Branch { id, weights: ref weights, members: ref members, next: ref next, ..} => {
+                    let total : f64 = match weights {
+                        &Some(ref w) => w.iter().sum(),
+                        &None => members.len() as f64
+                    };
+                    let mut c = Column::empty( n );
+                    for (k, member) in members.iter().enumerate() {
+                        let prior = (weights.as_ref().map( |w| w[k] ).unwrap_or(1.) / total).ln();
+                        let b = self.back( member, &**next, beta.clone(), after );
+                        c.add( &b, prior );
+                    }
+                    after.insert( id, beta );
+                    c
+                },
+                &SeqNode::Nil => beta
+            };
+        }
+        beta
+    }
+}
+
+impl SeqGraph {
+    /// SeqGraph::posteriors -- for each branch (by id), the posterior probability of each of
+    ///   its members given the query, in member order.  This is a forward-style sum over
+    ///   every alignment to every path, rather than the single best: a score of
+    ///   params.equal is taken as a likelihood ratio of 2 (so the match score must be
+    ///   positive), branch weights are the members' priors (equal if not given), and a
+    ///   repeat's copy counts are equally likely.  A member's posterior is the probability
+    ///   of a path through it, so a nested branch's members sum to that of the member
+    ///   holding it.
+    ///
+    /// One forward and one backward pass cover every branch: a member's posterior is the
+    ///   forward column it ends in (entering the branch, through the member) against the
+    ///   backward column after the branch.  None for Local mode, if the query can't be
+    ///   aligned, or if quals don't hold one per query base.
+    pub fn posteriors(&self, query: &Sequence, quals: Option<&[u8]>, params: &AlnParams)
+                      -> Option<BTreeMap<u32, Vec<f64>>> {
+        if params.mode == AlnMode::Local || params.equal <= 0 {
+            return None;
+        }
+        let lambda = 2f64.ln() / params.equal as f64;
+        let scores = match quals {
+            Some(q) if q.len() != query.len() => return None,
+            Some(q) => q.iter().map( |&x| (lambda * phred_scale( params.equal, x ) as f64,
+                                           lambda * phred_scale( params.mismatch, x ) as f64) ).collect(),
+            None => vec![ (lambda * params.equal as f64, lambda * params.mismatch as f64); query.len() ]
+        };
+        let fb = Forward { query: query, scores: scores, open: lambda * params.gap_open as f64,
+                           ext: lambda * params.gap_ext as f64, mode: params.mode };
+
+        let mut exits = BTreeMap::new();
+        let last = fb.walk( self.root(), 0 as *const SeqNode, fb.start(), &mut exits );
+        let z = last.dot( &fb.end() );
+        if z == NEG_INFINITY {
+            return None;
+        }
+        let mut after = BTreeMap::new();
+        fb.back( self.root(), 0 as *const SeqNode, fb.end(), &mut after );
+
+        Some( exits.iter().map( |(&id, ends)| {
+            (id, ends.iter().map( |c| (c.dot( &after[&id] ) - z).exp() ).collect())
+        }).collect() )
+    }
+
+    /// SeqGraph::path_posteriors -- the (branch id, posterior) of the member a path takes
+    ///   at each branch along it, given posteriors as returned by SeqGraph::posteriors
+    pub fn path_posteriors(&self, path: &Path, post: &BTreeMap<u32, Vec<f64>>) -> Vec<(u32, f64)> {
+        let ids = &path.nodes;
+        let mut taken = Vec::new();
+        for (k, &id) in ids.iter().enumerate() {
+            if let (Some(&SeqNode::Branch { members: ref members, ..}), Some(p)) = (self.node( id ), post.get( &id )) {
+                let chosen = ids.get( k + 1 ).cloned();
+                if let Some(m) = members.iter().position( |n| n.iden() == chosen ) {
+                    taken.push( (id, p[m]) );
+                }
+            }
+        }
+        taken
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::BTreeMap;
+use seq::*;
+use align::*;
+use tree::*;
+
+
+/// MAPQ reported when no mapping quality is available
+pub const MAPQ_UNAVAILABLE : u8 = 255;
+
+// MAPQ is capped here, as by most short-read aligners
+const MAPQ_MAX : u8 = 60;
+
+/// mapq -- mapping quality from the best path's score and the runner-up's (see
+///   SeqGraph::align__global_max_scored): the Phred-scaled probability that the runner-up
+///   is the true path, taking a score of params.equal as a likelihood ratio of 2 (as for
+///   branch posteriors).  A tie gives 3; no runner-up, or a runner-up far behind, gives 60.
+///   MAPQ_UNAVAILABLE if the match score isn't positive.
+pub fn mapq( score: i64, runner_up: Option<i64>, params: &AlnParams ) -> u8 {
+    if params.equal <= 0 {
+        return MAPQ_UNAVAILABLE;
+    }
+    match runner_up {
+        Some(second) => {
+            let bits = (score - second) as f64 / params.equal as f64;
+            // P(wrong) = 1 / (1 + 2^bits)
+            let q = 10. * (1. + 2f64.powf( bits )).log10();
+            if q >= MAPQ_MAX as f64 { MAPQ_MAX } else { q.round() as u8 }
+        },
+        None => MAPQ_MAX
+    }
+}
+
+/// sam_reference -- (name, length) of the linear reference a path is reported against: the
+///   concatenated sequence of its fragments, named by their names joined with '|'
+pub fn sam_reference( graph: &SeqGraph, path: &Path ) -> (String, usize) {
+    let len = GraphPath::from_graph( graph, path ).segments().iter()
+        .map( |&(_, ref val)| val.len() )
+        .sum();
+    (graph.path_name( path, "|" ), len)
+}
+
+/// sam_header -- header lines for the given (name, length) references
+pub fn sam_header( refs: &[(String, usize)] ) -> String {
+    let mut s = String::from("@HD\tVN:1.6\tSO:unsorted\n");
+    for &(ref name, len) in refs {
+        s.push_str( &format!("@SQ\tSN:{}\tLN:{}\n", name, len) );
+    }
+    s.push_str( &format!("@PG\tID:myxo\tPN:myxo\tVN:{}\n", env!("CARGO_PKG_VERSION")) );
+    s
+}
+
+/// node_positions -- the reference positions an alignment covers, per fragment of its
+///   path, as `name:start-end` (0-based, end-exclusive, in the fragment's own coordinates)
+///   joined with ','.  None if no query base is aligned.
+pub fn node_positions( graph: &SeqGraph, path: &Path, padded_ref: &Sequence, padded_query: &Sequence ) -> Option<String> {
+    cigar( padded_ref, padded_query ).map( |(offset, ops)| {
+        let ref_span : usize = ops.iter().filter( |&&(_, op)| op == 'M' || op == 'D' ).map( |&(n, _)| n ).sum();
+        graph.node_spans( path, offset, offset + ref_span ).iter()
+            .map( |&(id, start, end)| format!("{}:{}-{}", graph.node_name(id), start, end) )
+            .collect::<Vec<String>>()
+            .join(",")
+    })
+}
+
+/// repeat_tag -- the copy count of each repeat along the path, as `name=count` joined with
+///   ',', or None if there are no repeats
+pub fn repeat_tag( graph: &SeqGraph, path: &Path ) -> Option<String> {
+    let copies = graph.repeat_copies( path );
+    if copies.is_empty() {
+        None
+    } else {
+        Some( copies.iter().map( |&(id, k)| format!("{}={}", graph.node_name(id), k) ).collect::<Vec<String>>().join(",") )
+    }
+}
+
+/// optional_tag -- whether each optional node along the path was taken, as `name=1` (taken)
+///   or `name=0` (skipped) joined with ',', or None if there are no optional nodes
+pub fn optional_tag( graph: &SeqGraph, path: &Path ) -> Option<String> {
+    let taken = graph.optionals( path );
+    if taken.is_empty() {
+        None
+    } else {
+        Some( taken.iter().map( |&(id, t)| format!("{}={}", graph.node_name(id), t as u8) ).collect::<Vec<String>>().join(",") )
+    }
+}
+
+/// posterior_tag -- the posterior probability (see SeqGraph::posteriors) of the member taken
+///   at each branch along the path, as `name=p` joined with ',', or None if there are no
+///   branches
+pub fn posterior_tag( graph: &SeqGraph, path: &Path, post: &BTreeMap<u32, Vec<f64>> ) -> Option<String> {
+    let taken = graph.path_posteriors( path, post );
+    if taken.is_empty() {
+        None
+    } else {
+        Some( taken.iter().map( |&(id, p)| format!("{}={:.3}", graph.node_name(id), p) ).collect::<Vec<String>>().join(",") )
+    }
+}
+
+/// sam_record -- one SAM line (without trailing newline) for a query aligned to a graph path
+///
+/// `padded_ref`/`padded_query` are as returned by the graph aligners; `qual` holds Phred
+///   scores without the ASCII offset.  Secondary alignments are flagged 0x100.  The graph
+///   path is given in the `XP` tag, as '>'-separated node names, the positions covered
+///   in each fragment in the `XN` tag (see node_positions), repeat copy counts, if any,
+///   in the `XR` tag (see repeat_tag), and whether optional nodes were taken in the `XO`
+///   tag (see optional_tag).
+///
+pub fn sam_record( graph: &SeqGraph, name: &str, query: &Sequence, qual: Option<&[u8]>, path: &Path,
+                   padded_ref: &Sequence, padded_query: &Sequence, mapq: u8, secondary: bool, params: &AlnParams ) -> String {
+    match cigar( padded_ref, padded_query ) {
+        Some((offset, ops)) => {
+            let (rname, _) = sam_reference( graph, path );
+            let xr = repeat_tag( graph, path ).map( |t| format!("\tXR:Z:{}", t) ).unwrap_or_default();
+            let xo = optional_tag( graph, path ).map( |t| format!("\tXO:Z:{}", t) ).unwrap_or_default();
+            format!("{}\t{}\t{}\t{}\t{}\t{}\t*\t0\t0\t{}\t{}\tAS:i:{}\tNM:i:{}\tXP:Z:{}\tXN:Z:{}{}{}",
+                    name, if secondary { 0x100 } else { 0 }, rname, offset + 1, mapq, cigar_string( &ops ),
+                    seq_field( query ), qual_field( qual ),
+                    score_padded( padded_ref, padded_query, params ),
+                    edit_count( padded_ref, padded_query ),
+                    path.nodes.iter().map( |&id| format!(">{}", graph.node_name(id)) ).collect::<String>(),
+                    node_positions( graph, path, padded_ref, padded_query ).unwrap_or_default(),
+                    xr, xo)
+        },
+        None => sam_unmapped( name, query, qual )
+    }
+}
+
+/// sam_unmapped -- SAM line for a query with no alignment (flag 4), keeping its qualities
+pub fn sam_unmapped( name: &str, query: &Sequence, qual: Option<&[u8]> ) -> String {
+    format!("{}\t4\t*\t0\t0\t*\t*\t0\t0\t{}\t{}", name, seq_field( query ), qual_field( qual ))
+}
+
+// the SEQ and QUAL columns: '*' if empty or not given; qual is Phred scores without the
+//   ASCII offset
+fn seq_field( query: &Sequence ) -> String {
+    if query.len() > 0 { format!("{}", query) } else { "*".to_string() }
+}
+
+fn qual_field( qual: Option<&[u8]> ) -> String {
+    match qual {
+        Some(q) if q.len() > 0 => q.iter().map( |&x| (x.saturating_add(33)) as char ).collect(),
+        _ => "*".to_string()
+    }
+}
@@ -7,6 +7,8 @@ use std::ops::RangeTo;
 
 pub type Mmer = u8;
 pub const HYPHEN : Mmer = 4;
+/// WILDCARD: 'N', which matches any base
+pub const WILDCARD : Mmer = 5;
 
 #[derive(Clone,RustcDecodable,RustcEncodable)]
 pub struct Sequence( pub Vec<Mmer> );
@@ -18,9 +20,15 @@ pub fn base_to_char( ch : Mmer ) -> char {
         2 => 'G',
         3 => 'C',
         HYPHEN => '-',
+        WILDCARD => 'N',
         _ => 'X' }
 }
 
+/// bases_match -- whether two bases are equal, a wildcard matching any base (but not a gap)
+pub fn bases_match( a: Mmer, b: Mmer ) -> bool {
+    a == b || ((a == WILDCARD || b == WILDCARD) && a != HYPHEN && b != HYPHEN)
+}
+
 impl Sequence {
     pub fn from_str( seq : &str ) -> Result<Sequence, String> {
         let x = seq.to_uppercase()
@@ -31,6 +39,7 @@ impl Sequence {
                 'G' => Ok(2),
                 'C' => Ok(3),
                 '-' => Ok(HYPHEN),
+                'N' => Ok(WILDCARD),
                 _   => Err(format!("unrecognized base: {}", ch))
             } )
             .collect();
@@ -1,116 +1,252 @@
 extern crate serde_json;
 
 //use std::fmt::Write;
+use std::io;
 use std::io::Write;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use self::serde_json::Value as JSON_Val;
 use seq::*;
 use align::*;
 use matrix::*;
+use myers::{Peq, BitState};
 use std::iter::Iterator;
-use std::rc::Rc;
-use std::cmp::max;
+use std::sync::Arc;
+use std::cmp::{min, max};
 
 #[derive(Debug, Clone)]
 pub enum SeqNode {
     Nil,
 
+    // a `capture` fragment's aligned query bases are reported (see SeqGraph::captures)
     Frag {
         id: u32,
         val: Sequence,
         llocal: bool,
         rlocal: bool,
+        capture: bool,
 
-        next: Rc<SeqNode>
+        next: Arc<SeqNode>
     },
 
     //Dist { id: u32, scores: ProbMatr },
 
+    // `unit` repeated min ..= max times
+    Repeat {
+        id: u32,
+        unit: Sequence,
+        min: usize,
+        max: usize,
+
+        next: Arc<SeqNode>
+    },
+
+    // each member runs on into `next`, the sequence following the branch, which is shared
+    //   with the members rather than followed separately.  An `optional` node is a branch
+    //   of two members: the optional element, and a zero-length fragment that skips it.
+    //   `weights` are the members' prior probabilities, if given (see SeqGraph::prior_score)
     Branch {
         id: u32,
         llocal: bool,
         rlocal: bool,
-        members: Vec<SeqNode>
+        optional: bool,
+        weights: Option<Vec<f64>>,
+        members: Vec<Arc<SeqNode>>,
+
+        next: Arc<SeqNode>
     }
 }
 
-#[derive(Debug)]
+/// SeqGraph - a parsed graph-JSON reference
+///
+/// tails following a branch are shared between its members through `Arc`s, so a SeqGraph is
+/// `Send + Sync` and can be parsed once and aligned against from many threads (eg. behind an
+/// `Arc<SeqGraph>`).  Cloning is shallow.
+///
+/// Node ids are contiguous from 0, and assigned to fragments, repeats and branches in the
+/// order they appear in the graph-JSON text (a branch before its members).  An id therefore only
+/// changes if nodes are added or removed before it in the text; refer to nodes by their
+/// "id" name (see node_id) where the JSON may change.
+#[derive(Debug, Clone)]
 pub struct SeqGraph {
-    root: SeqNode,
-    names: BTreeMap<u32, String>
+    root: Arc<SeqNode>,
+    names: BTreeMap<u32, String>,
+    // name -> id of the first node with that name
+    ids: BTreeMap<String, u32>,
+    // id -> node
+    nodes: Vec<Arc<SeqNode>>
+}
+
+/// Path - a route through a SeqGraph: the ids of the nodes it passes through, in order (a
+///   branch's id is followed by the chosen member's), and the copy count taken of each
+///   repeat on it, by repeat id.  A repeat with no count is taken its min number of times.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Path {
+    pub nodes: Vec<u32>,
+    pub copies: BTreeMap<u32, usize>
 }
 
-/// GraphPath - used for Iterator trait on SeqGraph
+/// GraphPath - used for Iterator trait on SeqGraph, walking the fragments and repeats of a Path
 #[derive(Debug)]
 pub struct GraphPath<'a> {
     curr_node: &'a SeqNode,
-    path: Vec<u32>,
+    path: &'a Path,
     pos: usize
 }
 
+/// Haplotype - one source-to-sink path through a SeqGraph: its node ids (as taken by
+///   GraphPath), its name (see SeqGraph::path_name) and the sequence it spells
+#[derive(Debug, Clone, PartialEq)]
+pub struct Haplotype {
+    pub path: Path,
+    pub name: String,
+    pub seq: Sequence
+}
+
+/// Haplotypes - iterator over every source-to-sink path of a SeqGraph, in member order
+///
+/// paths are walked depth-first, so memory is bounded by the graph's depth rather than the
+/// number of paths (see SeqGraph::path_count before enumerating a large graph).  Each copy
+/// count of a repeat is a separate path.
+pub struct Haplotypes<'a> {
+    graph: &'a SeqGraph,
+    stack: Vec<(&'a SeqNode, Path)>
+}
+
+/// GraphStats - summary of a SeqGraph, see SeqGraph::stats
+///
+/// haplotype lengths are None when the graph has no complete path (eg. an empty branch).
+///   `depth` is the deepest nesting of branches within branch members; `dists` counts
+///   probability-distribution nodes, which aren't yet supported by the parser.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GraphStats {
+    pub min_len: Option<usize>,
+    pub max_len: Option<usize>,
+    pub fragments: usize,
+    pub branches: usize,
+    pub repeats: usize,
+    pub dists: usize,
+    pub depth: usize,
+    pub total_seq: usize,
+    pub paths: u128
+}
+
 #[derive(Debug)]
 pub enum SeqErr {
     BadJsonElement,
     Ambiguous,
     StringExpected,
     Unsupported,
-    Mismatch
+    Mismatch,
+    NotFound,
+    NoPath,
+    // a fragment or repeat unit that isn't a sequence of bases (see Sequence::from_str)
+    BadSequence(String)
 }
 
 
 impl SeqNode {
 
-    fn dispatch( idx: &mut u32, names : &mut BTreeMap<u32, String>, elem: &JSON_Val, next: Rc<SeqNode> )
-                 -> Result<SeqNode, SeqErr> {
-        *idx += 1;
+    // number of nodes (ids) an element will produce
+    fn size( elem: &JSON_Val ) -> u32 {
+        match elem {
+            &JSON_Val::String(_) => 1,
+            &JSON_Val::Array(ref l) => l.iter().map( SeqNode::size ).sum(),
+            &JSON_Val::Object(ref map) => match (map.get("branch"), map.get("optional")) {
+                (Some(&JSON_Val::Array(ref l)), _) => 1 + l.iter().map( SeqNode::size ).sum::<u32>(),
+                // the optional node, its element, and the fragment skipping it
+                (None, Some(x)) => 2 + SeqNode::size( x ),
+                _ => 1
+            },
+            _ => 0
+        }
+    }
 
+    // `idx` is the id of the element's first node: ids are assigned in pre-order, ie. in the
+    //   order nodes appear in the JSON text, whatever order they're built in
+    fn dispatch( idx: u32, names : &mut BTreeMap<u32, String>, elem: &JSON_Val, next: Arc<SeqNode> )
+                 -> Result<Arc<SeqNode>, SeqErr> {
         match elem {
             &JSON_Val::String(ref s) => SeqNode::read_str( idx, names, s, next ),
             &JSON_Val::Object(ref map) => SeqNode::read_obj( idx, names, map, next ),
-            &JSON_Val::Array(ref l) => SeqNode::read_list( idx, names, l, 0 ),
+            &JSON_Val::Array(ref l) => SeqNode::read_list( idx, names, l, 0, next ),
             _ => Err(SeqErr::BadJsonElement)
         }
     }
 
-    fn read_str( idx: &mut u32, names : &mut BTreeMap<u32, String>, s: &String, next: Rc<SeqNode> )
-                 -> Result<SeqNode, SeqErr> {
-        Ok(SeqNode::Frag{ id: *idx, val: Sequence::from_str(&s).unwrap(),
-                          llocal: false,
-                          rlocal: false,
-                          next: next.clone() })
+    fn read_str( idx: u32, names : &mut BTreeMap<u32, String>, s: &String, next: Arc<SeqNode> )
+                 -> Result<Arc<SeqNode>, SeqErr> {
+        Ok(Arc::new( SeqNode::Frag{ id: idx, val: try!( Sequence::from_str(&s).map_err( SeqErr::BadSequence ) ),
+                                    llocal: false,
+                                    rlocal: false,
+                                    capture: false,
+                                    next: next } ))
     }
 
-    fn read_list( idx: &mut u32, names : &mut BTreeMap<u32, String>, l: &Vec<JSON_Val>, pos: usize )
-                  -> Result<SeqNode, SeqErr> {
-        *idx += 1;
+    // a list is followed by `next`, so that a list nested in a branch continues into the
+    //   sequence following the branch
+    fn read_list( idx: u32, names : &mut BTreeMap<u32, String>, l: &Vec<JSON_Val>, pos: usize,
+                  next: Arc<SeqNode> ) -> Result<Arc<SeqNode>, SeqErr> {
         if pos < l.len() {
-            let next = SeqNode::read_list( idx, names, l, pos+1 ).unwrap();
-            SeqNode::dispatch( idx, names, &l[pos], Rc::new(next) )
+            let rest = try!( SeqNode::read_list( idx + SeqNode::size( &l[pos] ), names, l, pos+1, next ) );
+            SeqNode::dispatch( idx, names, &l[pos], rest )
         } else {
-            Ok( SeqNode::Nil )
+            Ok( next )
         }
     }
 
-    fn read_obj( idx: &mut u32, names: &mut BTreeMap<u32, String>, map: &BTreeMap<String, JSON_Val>, next: Rc<SeqNode> )
-                 -> Result<SeqNode, SeqErr> {
+    fn read_obj( idx: u32, names: &mut BTreeMap<u32, String>, map: &BTreeMap<String, JSON_Val>, next: Arc<SeqNode> )
+                 -> Result<Arc<SeqNode>, SeqErr> {
 
         let _ = match map.get("id") {
             Some(s) => {
                 match s {
-                    &JSON_Val::String(ref s2) => { names.insert(*idx, s2.clone()); () },
+                    &JSON_Val::String(ref s2) => { names.insert(idx, s2.clone()); () },
                     _ => return Err(SeqErr::StringExpected)
                 }},
             None => ()
         };
 
-        if map.contains_key("seq") {
+        if map.contains_key("optional") {
+            if map.contains_key("seq") || map.contains_key("dist") || map.contains_key("branch") || map.contains_key("repeat") {
+                return Err(SeqErr::Ambiguous);
+            }
+            // the node takes `idx`, its element follows, then the (unnamed) skip fragment
+            let elem = map.get("optional").unwrap();
+            let taken = try!( SeqNode::dispatch( idx + 1, names, elem, next.clone() ) );
+            let skip = Arc::new( SeqNode::Frag { id: idx + 1 + SeqNode::size( elem ), val: Sequence(Vec::new()),
+                                                 llocal: false, rlocal: false, capture: false, next: next.clone() } );
+            let weights = try!( SeqNode::read_weights( map, 2 ) );
+            Ok(Arc::new( SeqNode::Branch { id: idx, members: vec![ taken, skip ], optional: true, weights: weights,
+                                           llocal: false, rlocal: false, next: next } ))
+        } else if map.contains_key("repeat") {
+            if map.contains_key("seq") || map.contains_key("dist") || map.contains_key("branch") {
+                return Err(SeqErr::Ambiguous);
+            }
+            let count = |key: &str| match map.get(key) {
+                Some(v) => v.as_u64().map( |n| n as usize ).ok_or( SeqErr::BadJsonElement ),
+                None => Err(SeqErr::BadJsonElement)
+            };
+            let (lo, hi) = (try!( count("min") ), try!( count("max") ));
+            match map.get("repeat").unwrap() {
+                &JSON_Val::String(ref s2) if lo <= hi => {
+                    Ok(Arc::new( SeqNode::Repeat { id: idx, unit: try!( Sequence::from_str(&s2).map_err( SeqErr::BadSequence ) ),
+                                                   min: lo, max: hi, next: next } ))
+                },
+                _ => Err(SeqErr::BadJsonElement)
+            }
+        } else if map.contains_key("seq") {
             if map.contains_key("dist") || map.contains_key("branch") {
                 Err(SeqErr::Ambiguous)
             } else {
+                let capture = match map.get("capture") {
+                    Some(&JSON_Val::Bool(b)) => b,
+                    Some(_) => return Err(SeqErr::BadJsonElement),
+                    None => false
+                };
                 match map.get("seq").unwrap() {
                     &JSON_Val::String(ref s2) => {
-                        Ok( SeqNode::Frag { id: *idx, val: Sequence::from_str(&s2).unwrap(),
-                                            llocal: false, rlocal: false, next: next.clone() } )
+                        Ok(Arc::new( SeqNode::Frag { id: idx, val: try!( Sequence::from_str(&s2).map_err( SeqErr::BadSequence ) ),
+                                                     llocal: false, rlocal: false, capture: capture, next: next } ))
                     },
                     _ => Err(SeqErr::BadJsonElement)
                 }
@@ -120,20 +256,21 @@ impl SeqNode {
                 Err(SeqErr::Ambiguous)
             } else {
                 let members = match map.get("branch").unwrap() {
-                    &JSON_Val::Array(ref _l) => {
-                        let l : &Vec<JSON_Val> = _l;
+                    &JSON_Val::Array(ref l) => {
+                        // the branch takes `idx`, and its members follow it
                         let mut m = Vec::new();
+                        let mut member_idx = idx + 1;
                         for x in l {
-                            *idx += 1;
-                            m.push( SeqNode::dispatch( idx, names, x, next.clone() ).unwrap());
+                            m.push( try!( SeqNode::dispatch( member_idx, names, x, next.clone() ) ) );
+                            member_idx += SeqNode::size( x );
                         }
-                        Ok(m)
-                        //Ok( l.iter().map(|x| SeqNode::dispatch( idx, names, x, next ).unwrap() ).collect() )
+                        m
                     },
-                    _ => Err(SeqErr::BadJsonElement)
-                }.unwrap();
-                *idx += 1;
-                Ok( SeqNode::Branch { id: *idx, members: members, llocal: false, rlocal: false } )
+                    _ => return Err(SeqErr::BadJsonElement)
+                };
+                let weights = try!( SeqNode::read_weights( map, members.len() ) );
+                Ok(Arc::new( SeqNode::Branch { id: idx, members: members, optional: false, weights: weights,
+                                               llocal: false, rlocal: false, next: next } ))
             }
         } else if map.contains_key("dist") {
             Err(SeqErr::Unsupported)
@@ -142,10 +279,23 @@ impl SeqNode {
         }
     }
 
+    // a branch's "weights": one positive number per member
+    fn read_weights( map: &BTreeMap<String, JSON_Val>, n_members: usize ) -> Result<Option<Vec<f64>>, SeqErr> {
+        match map.get("weights") {
+            Some(&JSON_Val::Array(ref l)) if l.len() == n_members => {
+                let w : Vec<f64> = l.iter().filter_map( |x| x.as_f64() ).filter( |&x| x > 0.0 ).collect();
+                if w.len() == n_members { Ok(Some(w)) } else { Err(SeqErr::BadJsonElement) }
+            },
+            Some(_) => Err(SeqErr::BadJsonElement),
+            None => Ok(None)
+        }
+    }
+
     pub fn iden(&self) -> Option<u32> {
         match self {
             &SeqNode::Nil => None,
             &SeqNode::Frag { id: ref id, ..} => Some(id.clone()),
+            &SeqNode::Repeat { id: ref id, ..} => Some(id.clone()),
             &SeqNode::Branch { id: ref id, ..} => Some(id.clone())
         }
     }
@@ -161,135 +311,974 @@ enum GraphAlnMode {
 
 impl SeqGraph {
     pub fn from_json( serialized : &str ) -> Result<SeqGraph, SeqErr> {
+        let value : JSON_Val = try!( serde_json::from_str(serialized).map_err( |_| SeqErr::BadJsonElement ) );
+        SeqGraph::from_value( &value )
+    }
+
+    fn from_value( value: &JSON_Val ) -> Result<SeqGraph, SeqErr> {
         let mut names : BTreeMap<u32, String> = BTreeMap::new();
-        let mut idx = 0;
-        
-        let value = serde_json::from_str(serialized).unwrap();
-        let mut idx = 0u32;
-        let tree = SeqNode::dispatch( &mut idx, &mut names, &value, Rc::new(SeqNode::Nil) ).unwrap();
-        Ok( SeqGraph { root: tree, names: names } )
+        let tree = try!( SeqNode::dispatch( 0, &mut names, value, Arc::new(SeqNode::Nil) ) );
+
+        let mut ids = BTreeMap::new();
+        for (&id, name) in names.iter() {
+            ids.entry( name.clone() ).or_insert( id );
+        }
+        let mut nodes = vec![ None; SeqNode::size( value ) as usize ];
+        SeqGraph::_index( &tree, &mut nodes );
+
+        Ok( SeqGraph { root: tree, names: names, ids: ids,
+                       nodes: nodes.into_iter().map( |n| n.expect("node ids should be contiguous") ).collect() } )
+    }
+
+    fn _index(node: &Arc<SeqNode>, nodes: &mut Vec<Option<Arc<SeqNode>>>) {
+        let mut node = node;
+        loop {
+            let id = match node.iden() {
+                Some(id) => id as usize,
+                None => return
+            };
+            if nodes[id].is_some() {
+                // a shared tail, already indexed
+                return;
+            }
+            nodes[id] = Some( node.clone() );
+            match **node {
+                SeqNode::Frag { next: ref next, ..} | SeqNode::Repeat { next: ref next, ..} => node = next,
+                SeqNode::Branch { members: ref members, next: ref next, ..} => {
+                    for n in members {
+                        SeqGraph::_index( n, nodes );
+                    }
+                    node = next;
+                },
+                SeqNode::Nil => return
+            }
+        }
+    }
+
+    // graph-JSON for the chain of nodes from `node` up to (not including) `stop`
+    fn _chain_json(&self, node: &SeqNode, stop: *const SeqNode) -> Vec<JSON_Val> {
+        let mut elems = Vec::new();
+        let mut node = node;
+        while node as *const SeqNode != stop {
+            let named = |id: u32, key: &str, val: JSON_Val| {
+                let mut m = BTreeMap::new();
+                if let Some(name) = self.names.get(&id) {
+                    m.insert( "id".to_string(), JSON_Val::String( name.clone() ) );
+                }
+                m.insert( key.to_string(), val );
+                JSON_Val::Object(m)
+            };
+            node = match node {
+                &SeqNode::Nil => break,
+                &SeqNode::Frag { id, val: ref val, capture, next: ref next, ..} => {
+                    let seq = JSON_Val::String( format!("{}", val) );
+                    elems.push( if capture {
+                        let mut f = named( id, "seq", seq );
+                        if let JSON_Val::Object(ref mut m) = f {
+                            m.insert( "capture".to_string(), JSON_Val::Bool(true) );
+                        }
+                        f
+                    } else if self.names.contains_key(&id) { named( id, "seq", seq ) } else { seq } );
+                    next
+                },
+                &SeqNode::Repeat { id, unit: ref unit, min: lo, max: hi, next: ref next } => {
+                    let mut r = named( id, "repeat", JSON_Val::String( format!("{}", unit) ) );
+                    if let JSON_Val::Object(ref mut m) = r {
+                        m.insert( "min".to_string(), JSON_Val::U64( lo as u64 ) );
+                        m.insert( "max".to_string(), JSON_Val::U64( hi as u64 ) );
+                    }
+                    elems.push( r );
+                    next
+                },
+                &SeqNode::Branch { id, optional, weights: ref weights, members: ref members, next: ref next, ..} => {
+                    let mut m : Vec<JSON_Val> = members.iter().map( |n| {
+                        let mut chain = self._chain_json( n, &**next );
+                        if chain.len() == 1 { chain.pop().unwrap() } else { JSON_Val::Array(chain) }
+                    }).collect();
+                    let mut b = if optional {
+                        named( id, "optional", m.swap_remove(0) )
+                    } else {
+                        named( id, "branch", JSON_Val::Array(m) )
+                    };
+                    if let (&mut JSON_Val::Object(ref mut o), &Some(ref w)) = (&mut b, weights) {
+                        o.insert( "weights".to_string(), JSON_Val::Array( w.iter().map( |&x| JSON_Val::F64(x) ).collect() ) );
+                    }
+                    elems.push( b );
+                    next
+                }
+            };
+        }
+        elems
+    }
+
+    /// SeqGraph::to_json -- graph-JSON for this graph.  Parsing the result gives a graph
+    ///   with the same ids, names and paths.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string( &JSON_Val::Array( self._chain_json( &self.root, 0 as *const SeqNode ) ) ).unwrap()
+    }
+
+    /// SeqGraph::subtree -- a new graph holding just the named node: a fragment, or a branch
+    ///   and its members (without the sequence following it)
+    pub fn subtree(&self, name: &str) -> Result<SeqGraph, SeqErr> {
+        self.between( name, name )
+    }
+
+    /// SeqGraph::between -- a new graph holding the nodes from `from` to `to` inclusive, both
+    ///   given by name.  `to` must follow `from` without entering a branch member (it may be
+    ///   after the end of the member `from` is in); otherwise Err(SeqErr::NoPath).  Names are
+    ///   preserved, and ids are renumbered from 0.
+    pub fn between(&self, from: &str, to: &str) -> Result<SeqGraph, SeqErr> {
+        let first = try!( self.node_id( from ).ok_or( SeqErr::NotFound ) );
+        let last = try!( self.node_id( to ).ok_or( SeqErr::NotFound ) );
+
+        // follow `next` from `first` until we reach `last`
+        let mut node = &*self.nodes[ first as usize ];
+        loop {
+            let next = match node {
+                &SeqNode::Frag { next: ref next, ..} | &SeqNode::Repeat { next: ref next, ..}
+                    | &SeqNode::Branch { next: ref next, ..} => next,
+                &SeqNode::Nil => return Err(SeqErr::NoPath)
+            };
+            if node.iden() == Some(last) {
+                let chain = self._chain_json( &*self.nodes[ first as usize ], &**next );
+                return SeqGraph::from_value( &JSON_Val::Array(chain) );
+            }
+            node = next;
+        }
+    }
+
+    /// SeqGraph::root -- the first node of the graph (Nil if it's empty)
+    pub fn root(&self) -> &SeqNode {
+        &*self.root
+    }
+
+    /// SeqGraph::node_count -- number of nodes; ids run from 0 to node_count() - 1
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// SeqGraph::node -- the fragment or branch with this id
+    pub fn node(&self, id: u32) -> Option<&SeqNode> {
+        self.nodes.get( id as usize ).map( |n| &**n )
     }
 
-    fn _max_len(n: &SeqNode) -> usize {
-        match n {
-            &SeqNode::Nil => 0,
-            &SeqNode::Frag { val: ref val, next: ref next, ..} => { val.len() + SeqGraph::_max_len(next) },
-            &SeqNode::Branch { members: ref members, ..} => { members.iter()
-                                                              .map( |n| SeqGraph::_max_len(n) )
-                                                              .max()
-                                                              .unwrap() }
+    /// SeqGraph::node_id -- id of the node with this "id" name.  Names needn't be unique
+    ///   (see validate); the first in the JSON text is returned.
+    pub fn node_id(&self, name: &str) -> Option<u32> {
+        self.ids.get( name ).cloned()
+    }
+
+    /// SeqGraph::name -- the node's "id" name, if it has one
+    pub fn name(&self, id: u32) -> Option<&str> {
+        self.names.get( &id ).map( |s| s.as_str() )
+    }
+
+    // (min, max) haplotype length from `n` to the end, or None if no path completes
+    fn _len_range(n: &SeqNode, memo: &mut HashMap<*const SeqNode, Option<(usize, usize)>>) -> Option<(usize, usize)> {
+        let key = n as *const SeqNode;
+        if let Some(&r) = memo.get(&key) {
+            return r;
         }
+        let r = match n {
+            &SeqNode::Nil => Some((0, 0)),
+            &SeqNode::Frag { val: ref val, next: ref next, ..} =>
+                SeqGraph::_len_range( next, memo ).map( |(lo, hi)| (lo + val.len(), hi + val.len()) ),
+            &SeqNode::Repeat { unit: ref unit, min: lo_n, max: hi_n, next: ref next, ..} =>
+                SeqGraph::_len_range( next, memo ).map( |(lo, hi)| (lo + lo_n * unit.len(), hi + hi_n * unit.len()) ),
+            &SeqNode::Branch { members: ref members, ..} => members.iter()
+                .filter_map( |n| SeqGraph::_len_range( n, memo ) )
+                .fold( None, |acc, (lo, hi)| match acc {
+                    Some((acc_lo, acc_hi)) => Some((min(acc_lo, lo), max(acc_hi, hi))),
+                    None => Some((lo, hi))
+                })
+        };
+        memo.insert( key, r );
+        r
     }
-    /// max_len - the maximum sequence length encoded by this graph
+
+    /// max_len - the maximum sequence length encoded by this graph (0 if it has no paths)
     pub fn max_len(&self) -> usize {
-        SeqGraph::_max_len( &self.root )
+        SeqGraph::_len_range( &self.root, &mut HashMap::new() ).map( |r| r.1 ).unwrap_or(0)
+    }
+
+    // count the nodes reachable from `node` before `stop` (the tail of the enclosing
+    //   branch), `depth` branches down
+    fn _stats(node: &SeqNode, stop: *const SeqNode, depth: usize, st: &mut GraphStats, seen: &mut HashSet<u32>) {
+        let mut node = node;
+        while node as *const SeqNode != stop {
+            match node {
+                &SeqNode::Nil => return,
+                &SeqNode::Frag { id, val: ref val, next: ref next, ..} => {
+                    if seen.insert( id ) {
+                        st.fragments += 1;
+                        st.total_seq += val.len();
+                    }
+                    node = next;
+                },
+                &SeqNode::Repeat { id, unit: ref unit, next: ref next, ..} => {
+                    if seen.insert( id ) {
+                        st.repeats += 1;
+                        st.total_seq += unit.len();
+                    }
+                    node = next;
+                },
+                &SeqNode::Branch { id, members: ref members, next: ref next, ..} => {
+                    if seen.insert( id ) {
+                        st.branches += 1;
+                    }
+                    st.depth = max( st.depth, depth + 1 );
+                    for n in members {
+                        SeqGraph::_stats( n, &**next as *const SeqNode, depth + 1, st, seen );
+                    }
+                    node = next;
+                }
+            }
+        }
+    }
+
+    /// SeqGraph::stats -- haplotype lengths, node counts, nesting depth, total fragment
+    ///   sequence and number of paths
+    pub fn stats(&self) -> GraphStats {
+        let range = SeqGraph::_len_range( &self.root, &mut HashMap::new() );
+        let mut st = GraphStats { min_len: range.map( |r| r.0 ), max_len: range.map( |r| r.1 ),
+                                  fragments: 0, branches: 0, repeats: 0, dists: 0, depth: 0, total_seq: 0,
+                                  paths: self.path_count() };
+        SeqGraph::_stats( &self.root, 0 as *const SeqNode, 0, &mut st, &mut HashSet::new() );
+        st
+    }
+
+    /// SeqGraph::paths -- iterate over every source-to-sink path (haplotype) of the graph
+    pub fn paths(&self) -> Haplotypes {
+        Haplotypes { graph: self, stack: vec![ (&*self.root, Path::default()) ] }
+    }
+
+    fn _path_count(node: &SeqNode, memo: &mut HashMap<*const SeqNode, u128>) -> u128 {
+        let key = node as *const SeqNode;
+        if let Some(&n) = memo.get(&key) {
+            return n;
+        }
+        let n = match node {
+            &SeqNode::Nil => 1,
+            &SeqNode::Frag { next: ref next, ..} => SeqGraph::_path_count( next, memo ),
+            &SeqNode::Repeat { min: lo, max: hi, next: ref next, ..} =>
+                ((hi - lo + 1) as u128).saturating_mul( SeqGraph::_path_count( next, memo ) ),
+            &SeqNode::Branch { members: ref members, ..} => members.iter()
+                .fold( 0u128, |acc, n| acc.saturating_add( SeqGraph::_path_count( n, memo ) ) )
+        };
+        memo.insert( key, n );
+        n
+    }
+
+    /// SeqGraph::path_count -- number of source-to-sink paths, without enumerating them.
+    ///   Shared tails are counted once, so this is linear in the size of the graph; the count
+    ///   saturates at u128::MAX.
+    pub fn path_count(&self) -> u128 {
+        SeqGraph::_path_count( &self.root, &mut HashMap::new() )
+    }
+
+    /// SeqGraph::write_fasta -- write every haplotype as a FASTA record, named as by
+    ///   path_name and wrapped at `width` bases per line (0 for no wrapping)
+    pub fn write_fasta<W: Write>(&self, out: &mut W, width: usize) -> io::Result<()> {
+        for h in self.paths() {
+            try!( writeln!( out, ">{}", h.name ) );
+            let s = format!("{}", h.seq);
+            if width == 0 || s.is_empty() {
+                try!( writeln!( out, "{}", s ) );
+            } else {
+                for line in s.as_bytes().chunks( width ) {
+                    try!( out.write_all( line ) );
+                    try!( out.write_all( b"\n" ) );
+                }
+            }
+        }
+        Ok(())
     }
 
-    fn _align(node: &SeqNode, query: &Sequence, m: &mut Matrix<Cell>, base_params: &AlnParams,
-              start: i32, path: &mut Vec<u32>, pos: usize, mode: GraphAlnMode )
-              -> Option<(/*score*/ i32, /*path*/ Vec<u32>)> {
+    /// node_name - the node's "id" from the graph-JSON, or its numeric id if it has none
+    pub fn node_name(&self, id: u32) -> String {
+        match self.names.get(&id) {
+            Some(name) => name.clone(),
+            None => id.to_string()
+        }
+    }
+
+    /// SeqGraph::to_node -- the fragment a 0-based offset into a path's sequence falls in,
+    ///   and the offset within that fragment.  None if the offset is past the path's end.
+    pub fn to_node(&self, path: &Path, offset: usize) -> Option<(/*id*/ u32, /*offset*/ usize)> {
+        let mut seg_start = 0;
+        for (id, val) in GraphPath::from_graph( self, path ).segments() {
+            if offset < seg_start + val.len() {
+                return Some((id, offset - seg_start));
+            }
+            seg_start += val.len();
+        }
+        None
+    }
+
+    /// SeqGraph::from_node -- the inverse of to_node: the offset into a path's sequence of
+    ///   an offset within one of its fragments.  None if the fragment isn't on the path, or
+    ///   is shorter than the offset.
+    pub fn from_node(&self, path: &Path, id: u32, offset: usize) -> Option<usize> {
+        let mut seg_start = 0;
+        for (seg_id, val) in GraphPath::from_graph( self, path ).segments() {
+            if seg_id == id {
+                return if offset < val.len() { Some( seg_start + offset ) } else { None };
+            }
+            seg_start += val.len();
+        }
+        None
+    }
+
+    /// SeqGraph::node_spans -- the fragments overlapping [start, end) of a path's sequence,
+    ///   each as (id, start, end) in that fragment's own coordinates
+    pub fn node_spans(&self, path: &Path, start: usize, end: usize) -> Vec<(u32, usize, usize)> {
+        let mut spans = Vec::new();
+        let mut seg_start = 0;
+        for (id, val) in GraphPath::from_graph( self, path ).segments() {
+            let seg_end = seg_start + val.len();
+            if seg_end > start && seg_start < end {
+                spans.push( (id, max( start, seg_start ) - seg_start, min( end, seg_end ) - seg_start) );
+            }
+            seg_start = seg_end;
+        }
+        spans
+    }
+
+    /// path_name - name of the linear sequence spelled by a path: the names of its
+    ///   fragments joined by `sep`.  Unnamed zero-length fragments (eg. the skip of an
+    ///   optional node) are left out.
+    pub fn path_name(&self, path: &Path, sep: &str) -> String {
+        let names : Vec<String> = GraphPath::from_graph( self, path ).segments().iter()
+            .filter( |&&(id, ref val)| val.len() > 0 || self.names.contains_key(&id) )
+            .map( |&(id, _)| self.node_name(id) )
+            .collect();
+        names.join(sep)
+    }
 
+    // fill the DP columns for a fragment or repeat starting after column `start`, adding its
+    //   id to `path`; returns its last column and the node that follows it.  A repeat's
+    //   copies are laid out up to its max, and its last column is overwritten with, per row,
+    //   the best of its allowed exits (see _merge_exits, whose result is also returned); its
+    //   copy count is left for _trace_path to settle.
+    fn _fill<'a, S: CellScore>(node: &'a SeqNode, query: &Sequence, quals: Option<&[u8]>, m: &mut Matrix<Cell<S>>,
+                               params: &AlnParams, start: i32, path: &mut Vec<u32>)
+                               -> Result<(/*end*/ i32, /*next*/ &'a SeqNode, /*exits*/ Vec<usize>), AlnErr> {
         match node {
-            &SeqNode::Nil => Some((0, path.clone())),
-            &SeqNode::Frag { id: ref id, val: ref val, next: ref next, ..} => {
-                path.push( id.clone() );
-                let t = align_matrix( val, query, &base_params, Some(start), m ).unwrap();
+            &SeqNode::Frag { id, val: ref val, next: ref next, ..} => {
+                path.push( id );
+                // a zero-length fragment (eg. an optional node's skip) fills no columns
+                if val.len() > 0 {
+                    try!( align_matrix_qual( val, query, quals, params, Some(start), m ) );
+                }
+                Ok((start + val.len() as i32, next, Vec::new()))
+            },
+            &SeqNode::Repeat { id, unit: ref unit, min: lo, max: hi, next: ref next } => {
+                path.push( id );
+                let u = unit.len() as i32;
+                for k in 0 .. hi as i32 {
+                    try!( align_matrix_qual( unit, query, quals, params, Some(start + k * u), m ) );
+                }
+                let exits = SeqGraph::_merge_exits( m, start, u, lo, hi, query.len() as i32 );
+                Ok((start + hi as i32 * u, next, exits))
+            },
+            _ => panic!("_fill: expected a fragment or repeat")
+        }
+    }
+
+    // overwrite the last column of a repeat's copies (starting after column `start`) with the
+    //   best cell, per row, among the ends of copies lo ..= hi; returns which copy count won
+    //   for each row.
+    fn _merge_exits<S: CellScore>(m: &mut Matrix<Cell<S>>, start: i32, u: i32, lo: usize, hi: usize, query_len: i32)
+                                  -> Vec<usize> {
+        let last = start + hi as i32 * u;
+        (0 .. query_len + 1).map( |j| {
+            let mut best_k = hi;
+            let mut best = Cell::unpack_wide( &m[ (last, j) ] ).unwrap().1;
+            for k in lo .. hi {
+                let score = Cell::unpack_wide( &m[ (start + k as i32 * u, j) ] ).unwrap().1;
+                if score > best {
+                    best = score;
+                    best_k = k;
+                }
+            }
+            if best_k != hi {
+                m[ (last, j) ] = m[ (start + best_k as i32 * u, j) ].clone();
+            }
+            best_k
+        }).collect()
+    }
+
+    // the best score from `node` on, and its path; also the runner-up: the best score of
+    //   an alignment taking another member at one of the branches the best one reaches
+    //   (i64::min_value() if there's none)
+    fn _align<S: CellScore>(node: &SeqNode, query: &Sequence, quals: Option<&[u8]>, m: &mut Matrix<Cell<S>>,
+              base_params: &AlnParams, start: i32, path: &mut Vec<u32>, pos: usize, mode: GraphAlnMode )
+              -> Result<(/*score*/ i64, /*runner_up*/ i64, /*path*/ Vec<u32>), AlnErr> {
+
+        let query_len = query.len() as i32;
+        match node {
+            // the end of the reference: apply the mode's end rules to the path's last column
+            &SeqNode::Nil => Ok(( match best_end( m, (start, start), query_len, true, base_params.mode ) {
+                                      Some((score, _)) => score,
+                                      None => i64::min_value() },
+                                  i64::min_value(),
+                                  path.clone() )),
+            &SeqNode::Frag { .. } | &SeqNode::Repeat { .. } => {
+                let (end, next, _) = try!( SeqGraph::_fill( node, query, quals, m, base_params, start, path ) );
+                let next_pos = path.len();
 
                 match mode {
                     GraphAlnMode::Global => {
-                        let score = Cell::unpack(&m[t]).unwrap().1;
-                        let (next_score, next_path) = SeqGraph::_align( next, query, m, base_params,
-                                                                        start + val.len() as i32, path,
-                                                                        pos + 1, mode )
-                            .unwrap();
-                        Some((max(score, next_score), next_path.to_vec()))
+                        // where the mode allows, the alignment may end within this fragment
+                        let score = match best_end( m, (start + 1, end), query_len, false, base_params.mode ) {
+                            Some((score, _)) => score,
+                            None => i64::min_value()
+                        };
+                        let (next_score, next_second, next_path) = try!( SeqGraph::_align( next, query, quals, m, base_params,
+                                                                                           end, path, next_pos, mode ) );
+                        // ending here, the branches after aren't reached, so don't compete
+                        if score > next_score {
+                            Ok((score, i64::min_value(), next_path.to_vec()))
+                        } else {
+                            Ok((next_score, next_second, next_path.to_vec()))
+                        }
                     },
-                    GraphAlnMode::LocalFollow => SeqGraph::_align( next, query, m, base_params, start + val.len() as i32,
-                                                                   path, pos+1, GraphAlnMode::LocalFollow ),
-                    GraphAlnMode::LocalTest => Some((Cell::unpack(&m[t]).unwrap().1, path.to_vec()))
+                    GraphAlnMode::LocalFollow => SeqGraph::_align( next, query, quals, m, base_params, end,
+                                                                   path, next_pos, GraphAlnMode::LocalFollow ),
+                    // a zero-length fragment has no cells of its own to test, so look past it
+                    GraphAlnMode::LocalTest if end == start =>
+                        SeqGraph::_align( next, query, quals, m, base_params, end, path, next_pos, GraphAlnMode::LocalTest ),
+                    GraphAlnMode::LocalTest => {
+                        let best = best_end( m, (start + 1, end), query_len, false, AlnMode::Local );
+                        Ok((best.map( |b| b.0 ).unwrap_or( i64::min_value() ), i64::min_value(), path.to_vec()))
+                    }
                 }
             },
-            &SeqNode::Branch { members: ref members, ..} => {
+            // members are compared with their prior scores added, which are carried on to
+            //   the enclosing branches' comparisons
+            &SeqNode::Branch { members: ref members, weights: ref weights, ..} => {
                 path.push( node.iden().unwrap() );
 
                 let mut best_node = &SeqNode::Nil;
-                let mut best_score = i32::min_value();
+                let mut best_score = i64::min_value();
+                let mut best_prior = 0;
+                // the best score of the members other than the best so far
+                let mut others = i64::min_value();
 
-                for n in members {
+                for (k, n) in members.iter().enumerate() {
                     path.truncate( pos + 1 );
-                    let (next_score, _) = SeqGraph::_align( n, query, m, base_params, start, path, pos + 1,
-                                                            match mode { GraphAlnMode::Global => GraphAlnMode::Global,
-                                                                         _ => GraphAlnMode::LocalTest } )
-                        .unwrap();
-                    if next_score > best_score {
-                        best_score = next_score;
+                    let (next_score, _, _) = try!( SeqGraph::_align( n, query, quals, m, base_params, start, path, pos + 1,
+                                                                     match mode { GraphAlnMode::Global => GraphAlnMode::Global,
+                                                                                  _ => GraphAlnMode::LocalTest } ) );
+                    let prior = SeqGraph::prior_score( weights, k, base_params );
+                    let score = next_score.saturating_add( prior );
+                    if score > best_score {
+                        others = max( others, best_score );
+                        best_score = score;
                         best_node = &n;
+                        best_prior = prior;
+                    } else {
+                        others = max( others, score );
                     }
                 }
                 path.truncate( pos + 1 );
-                SeqGraph::_align( best_node, query, m, base_params, start, path, pos+1, mode )
+                let (score, second, best_path) = try!( SeqGraph::_align( best_node, query, quals, m, base_params, start, path, pos+1, mode ) );
+                Ok((score.saturating_add( best_prior ), max( second.saturating_add( best_prior ), others ), best_path))
             }
         }
     }
 
+    /// SeqGraph::prior_score -- the score bonus (<= 0) for taking member `k` of a branch with
+    ///   these prior weights: log2 of its weight relative to the heaviest member's, in units
+    ///   of the match score.  The likeliest member costs nothing, and each halving of the
+    ///   prior costs one match.  Weights needn't sum to 1; no weights give no bonus.
+    pub fn prior_score(weights: &Option<Vec<f64>>, k: usize, params: &AlnParams) -> i64 {
+        match weights {
+            &Some(ref w) => {
+                let top = w.iter().cloned().fold( 0.0, f64::max );
+                ((w[k] / top).log2() * params.equal as f64).round() as i64
+            },
+            &None => 0
+        }
+    }
 
-    /// SeqGraph::align__global_max -- align query to graph, testing every possible branch to
-    ///   find the global maximum.
-    pub fn align__global_max(&self, query: &Sequence, base_params: &AlnParams )
-                            -> Option<(/*path*/ Vec<u32>, /*padded_ref*/ Sequence, /*padded_query*/ Sequence)> {
-        let mut _path = Vec::new();
-        let ref_len = self.max_len();
-        let mut m = Matrix::<Cell>::new( Cell(0), ref_len + 2, query.len() + 2 );
-        match SeqGraph::_align( &self.root, query,  &mut m, base_params, 0,
-                                 &mut _path, 0, GraphAlnMode::Global ) {
-            Some((score, path)) => {
-                let mut full_ref_v = Vec::new();
-                for s in GraphPath::from_graph( self, path.to_vec() ) {
-                    full_ref_v.extend( s.0 );
+    /// SeqGraph::path_seq -- the concatenated sequence of the fragments along a path
+    pub fn path_seq(&self, path: &Path) -> Sequence {
+        let mut full_ref_v = Vec::new();
+        for s in GraphPath::from_graph( self, path ) {
+            full_ref_v.extend( s.0 );
+        }
+        Sequence(full_ref_v)
+    }
+
+    // the padded (reference, query) alignment of query to `path`, settling the copy count
+    //   of each repeat on it: with the branches fixed, one more DP along the path, then a
+    //   traceback which, on reaching the last column of a repeat's copies, reads off which
+    //   copy count that row's best exit came from and jumps there.  Repeats the alignment
+    //   doesn't reach get their min count.
+    fn _trace_path<S: CellScore>(&self, path: &mut Path, query: &Sequence, quals: Option<&[u8]>,
+                                 params: &AlnParams) -> Result<(Sequence, Sequence), AlnErr> {
+        let nodes : Vec<&SeqNode> = path.nodes.iter().filter_map( |&id| self.node( id ) )
+            .filter( |n| match *n { &SeqNode::Frag { .. } | &SeqNode::Repeat { .. } => true, _ => false } )
+            .collect();
+
+        // the reference base of each column, with every repeat laid out to its max
+        let mut layout : Vec<Mmer> = Vec::new();
+        for &node in nodes.iter() {
+            match node {
+                &SeqNode::Frag { val: ref val, ..} => layout.extend( val.0.iter().cloned() ),
+                &SeqNode::Repeat { unit: ref unit, max: hi, ..} => for _ in 0 .. hi {
+                    layout.extend( unit.0.iter().cloned() );
+                },
+                _ => ()
+            }
+        }
+        let query_len = query.len() as i32;
+        let mut m = Matrix::<Cell<S>>::new( Cell::nil(), layout.len() + 2, query.len() + 2 );
+
+        // column 0 (the query against nothing) first, so that ties go to the earliest end
+        //   as in a linear alignment
+        try!( align_matrix_qual( &Sequence(Vec::new()), query, quals, params, None, &mut m ) );
+        let mut best = best_end( &m, (0, 0), query_len, false, params.mode );
+        let consider = |b: Option<(i64, (i32, i32))>, best: &mut Option<(i64, (i32, i32))>| {
+            if let Some(x) = b {
+                if best.map( |y| x.0 > y.0 ).unwrap_or(true) {
+                    *best = Some(x);
                 }
-                let full_ref = Sequence(full_ref_v);
+            }
+        };
 
-                // FIXME - we can't use the existing alignment matrix, because we don't
-                //   know what path was tested last, and it's unlikely to be the maximum.
-                //   however, we can probably do better than this
-                let padded = align( &full_ref, query, base_params ).unwrap();
-                Some((path, padded.0, padded.1))
-            },
-            None => None
+        // (repeat id, first column, unit length, min, max, best count per row)
+        let mut repeats : Vec<(u32, i32, i32, usize, usize, Vec<usize>)> = Vec::new();
+        let mut col = 0;
+        for &node in nodes.iter() {
+            let mut scratch = Vec::new();
+            let (end, _, exits) = try!( SeqGraph::_fill( node, query, quals, &mut m, params, col, &mut scratch ) );
+            if let &SeqNode::Repeat { id, unit: ref unit, min: lo, max: hi, .. } = node {
+                repeats.push( (id, col, unit.len() as i32, lo, hi, exits) );
+                path.copies.insert( id, lo );
+            }
+            consider( best_end( &m, (col + 1, end), query_len, false, params.mode ), &mut best );
+            col = end;
+        }
+        consider( best_end( &m, (col, col), query_len, true, params.mode ), &mut best );
+
+        let end = best.map( |b| b.1 ).unwrap_or( (col, query_len) );
+        let (mut i, mut j) = end;
+        let mut core_r : Vec<Mmer> = Vec::new();
+        let mut core_q : Vec<Mmer> = Vec::new();
+        loop {
+            // the trace is at or past the end of the last repeat not yet passed
+            while let Some(r) = repeats.pop() {
+                let last = r.1 + r.4 as i32 * r.2;
+                if i > last {
+                    repeats.push( r );
+                    break;
+                } else if i == last {
+                    let k = r.5[ j as usize ];
+                    path.copies.insert( r.0, k );
+                    i = r.1 + k as i32 * r.2;
+                } else if i > r.1 && r.2 > 0 {
+                    // the alignment ends within the copies: count those it reaches
+                    let reached = ((i - r.1 + r.2 - 1) / r.2) as usize;
+                    path.copies.insert( r.0, max( r.3, reached ) );
+                }
+            }
+            if i <= 0 && j <= 0 {
+                break;
+            }
+            match Cell::unpack_wide( &m[ (i, j) ] ).unwrap().0 {
+                AlnState::Match | AlnState::Mismatch if i > 0 && j > 0 => {
+                    core_r.push( layout[ (i - 1) as usize ] );
+                    core_q.push( query[j-1] );
+                    i -= 1;
+                    j -= 1;
+                },
+                AlnState::Del if i > 0 => {
+                    core_r.push( layout[ (i - 1) as usize ] );
+                    core_q.push( HYPHEN );
+                    i -= 1;
+                },
+                AlnState::Ins if j > 0 => {
+                    core_r.push( HYPHEN );
+                    core_q.push( query[j-1] );
+                    j -= 1;
+                },
+                _ => break
+            }
+        }
+        core_r.reverse();
+        core_q.reverse();
+
+        // with the counts settled, where the trace's first and last columns fall in the
+        //   path's sequence: (first column, last column, offset into the path's sequence,
+        //   length taken) of each node
+        let mut spans = Vec::with_capacity( nodes.len() );
+        let (mut col, mut offset) = (0, 0);
+        for &node in nodes.iter() {
+            let (width, len) = match node {
+                &SeqNode::Frag { val: ref val, ..} => (val.len(), val.len()),
+                &SeqNode::Repeat { id, unit: ref unit, min: lo, max: hi, ..} => (unit.len() * hi, unit.len() * path.count( id, lo )),
+                _ => (0, 0)
+            };
+            spans.push( (col, col + width, offset, len) );
+            col += width;
+            offset += len;
+        }
+        let to_path = |c: i32| spans.iter().find( |&&(s, e, _, _)| s <= c as usize && c as usize <= e )
+            .map( |&(s, _, offset, len)| offset + min( c as usize - s, len ) ).unwrap_or(0);
+
+        let full_ref = self.path_seq( path );
+        let (start, stop) = (to_path( i ), to_path( end.0 ));
+        let mut padded_ref : Vec<Mmer> = Vec::with_capacity( full_ref.len() + query.len() );
+        let mut padded_query : Vec<Mmer> = Vec::with_capacity( full_ref.len() + query.len() );
+        pad_unaligned( &mut padded_ref, &mut padded_query, &full_ref.0[.. start], &query.0[.. j as usize] );
+        padded_ref.extend( core_r );
+        padded_query.extend( core_q );
+        pad_unaligned( &mut padded_ref, &mut padded_query, &full_ref.0[stop ..], &query.0[end.1 as usize ..] );
+        Ok((Sequence(padded_ref), Sequence(padded_query)))
+    }
+
+    /// SeqGraph::repeat_copies -- the (repeat id, copy count) of each repeat along a path
+    pub fn repeat_copies(&self, path: &Path) -> Vec<(u32, usize)> {
+        let mut walk = GraphPath::from_graph( self, path );
+        let mut copies = Vec::new();
+        while let Some(node) = walk._next_node() {
+            if let &SeqNode::Repeat { id, min: lo, .. } = node {
+                copies.push( (id, path.count( id, lo )) );
+            }
+        }
+        copies
+    }
+
+    /// SeqGraph::optionals -- the (optional node id, whether its element was taken) of each
+    ///   optional node along a path
+    pub fn optionals(&self, path: &Path) -> Vec<(u32, bool)> {
+        let ids = &path.nodes;
+        let mut taken = Vec::new();
+        for (k, &id) in ids.iter().enumerate() {
+            if let Some(&SeqNode::Branch { optional: true, members: ref members, ..}) = self.node( id ) {
+                taken.push( (id, ids.get( k + 1 ).cloned() == members[0].iden()) );
+            }
+        }
+        taken
+    }
+
+    /// SeqGraph::branch_members -- the (branch id, member id) taken at each branch along a
+    ///   path, in path order, with the [start, end) of the path's sequence the member spells
+    pub fn branch_members(&self, path: &Path) -> Vec<(u32, u32, usize, usize)> {
+        let mut out = Vec::new();
+        let (mut k, mut pos) = (0, 0);
+        self._branch_members( &self.root, 0 as *const SeqNode, path, &mut k, &mut pos, &mut out );
+        out
+    }
+
+    fn _branch_members(&self, node: &SeqNode, stop: *const SeqNode, path: &Path, k: &mut usize, pos: &mut usize,
+                       out: &mut Vec<(u32, u32, usize, usize)>) {
+        let mut node = node;
+        while node as *const SeqNode != stop && *k < path.nodes.len() {
+            node = match node {
+                &SeqNode::Nil => break,
+                &SeqNode::Frag { val: ref val, next: ref next, ..} => {
+                    *k += 1;
+                    *pos += val.len();
+                    next
+                },
+                &SeqNode::Repeat { id, unit: ref unit, min: lo, next: ref next, ..} => {
+                    *pos += unit.len() * path.count( id, lo );
+                    *k += 1;
+                    next
+                },
+                &SeqNode::Branch { id, members: ref members, next: ref next, ..} => {
+                    *k += 1;
+                    let chosen = match path.nodes.get( *k ).and_then( |&m| members.iter().find( |n| n.iden() == Some(m) ) ) {
+                        Some(n) => n,
+                        None => break
+                    };
+                    let (idx, start) = (out.len(), *pos);
+                    out.push( (id, chosen.iden().unwrap(), start, start) );
+                    self._branch_members( chosen, &**next, path, k, pos, out );
+                    out[idx].3 = *pos;
+                    next
+                }
+            };
         }
     }
 
+    /// SeqGraph::classify -- for each branch along an aligned path, the member the query was
+    ///   assigned to, or None if the alignment doesn't reach it: a member with sequence must
+    ///   overlap the aligned part of the reference, and an empty one (eg. a skipped optional)
+    ///   must lie strictly within it.  padded_ref/padded_query are as returned with the path.
+    pub fn classify(&self, path: &Path, padded_ref: &Sequence, padded_query: &Sequence) -> Vec<(u32, Option<u32>)> {
+        let span = cigar( padded_ref, padded_query ).map( |(offset, ops)| {
+            let len : usize = ops.iter().filter( |&&(_, op)| op == 'M' || op == 'D' ).map( |&(n, _)| n ).sum();
+            (offset, offset + len)
+        });
+        self.branch_members( path ).iter().map( |&(id, member, start, end)| {
+            let reached = match span {
+                Some((s, e)) => if start < end { s < end && e > start } else { s < start && start < e },
+                None => false
+            };
+            (id, if reached { Some(member) } else { None })
+        }).collect()
+    }
+
+    /// SeqGraph::captures -- for each capture fragment along an aligned path, the query bases
+    ///   aligned to it, and their qualities if given.  Bases inserted within the fragment or
+    ///   at its ends are included (against wildcards, an insertion's place is arbitrary), and
+    ///   only the aligned part of the query counts: a fragment the alignment doesn't cover
+    ///   captures nothing.
+    ///   padded_ref/padded_query are as returned with the path; quals, if given, must hold
+    ///   one per query base, or this returns Err(AlnErr::QualLength).
+    pub fn captures(&self, path: &Path, padded_ref: &Sequence, padded_query: &Sequence, quals: Option<&[u8]>)
+                    -> Result<Vec<(/*id*/ u32, /*seq*/ Sequence, /*qual*/ Option<Vec<u8>>)>, AlnErr> {
+        let query_len = padded_query.0.iter().filter( |&&c| c != HYPHEN ).count();
+        if quals.map( |qs| qs.len() != query_len ).unwrap_or(false) {
+            return Err(AlnErr::QualLength);
+        }
+        let mut spans = Vec::new();
+        let mut seg_start = 0;
+        for (id, val) in GraphPath::from_graph( self, path ).segments() {
+            if let Some(&SeqNode::Frag { capture: true, .. }) = self.node( id ) {
+                spans.push( (id, seg_start, seg_start + val.len()) );
+            }
+            seg_start += val.len();
+        }
+
+        let cols = aligned_columns( padded_ref, padded_query );
+        Ok( spans.iter().map( |&(id, start, end)| {
+            let mut seq = Vec::new();
+            let mut qual = Vec::new();
+            let (mut r, mut q) = (0, 0);
+            for k in 0 .. min( padded_ref.len(), padded_query.len() ) {
+                let (rb, qb) = (padded_ref.0[k], padded_query.0[k]);
+                let within = if rb == HYPHEN { start <= r && r <= end } else { start <= r && r < end };
+                if within && qb != HYPHEN && cols.map( |(a, b)| a <= k && k < b ).unwrap_or(false) {
+                    seq.push( qb );
+                    if let Some(qs) = quals {
+                        qual.push( qs[q] );
+                    }
+                }
+                if rb != HYPHEN { r += 1; }
+                if qb != HYPHEN { q += 1; }
+            }
+            (id, Sequence(seq), quals.map( |_| qual ))
+        }).collect() )
+    }
+
+    // the best-scoring path under `mode`, its alignment, and its score and the runner-up's
+    //   (see _align)
+    fn _align_padded<S: CellScore>(&self, query: &Sequence, quals: Option<&[u8]>, base_params: &AlnParams,
+                                   mode: GraphAlnMode )
+                                   -> Result<(/*path*/ Path, /*padded_ref*/ Sequence, /*padded_query*/ Sequence,
+                                              /*score*/ i64, /*runner_up*/ i64), AlnErr> {
+        let mut _path = Vec::new();
+        let ref_len = self.max_len();
+        let mut m = Matrix::<Cell<S>>::new( Cell::nil(), ref_len + 2, query.len() + 2 );
+        let (score, second, nodes) = try!( SeqGraph::_align( &self.root, query, quals, &mut m, base_params, 0,
+                                                             &mut _path, 0, mode ) );
+
+        // the matrix holds whichever path was tested last, so the best one is filled again
+        //   to trace the alignment back
+        let mut path = Path::from( nodes );
+        let (padded_ref, padded_query) = try!( self._trace_path::<S>( &mut path, query, quals, base_params ) );
+        Ok((path, padded_ref, padded_query, score, second))
+    }
+
+    /// SeqGraph::align__global_max -- align query to graph, testing every possible branch to
+    ///   find the global maximum.  Where a branch has weights, each member's prior score (see
+    ///   prior_score) is added in choosing between them.  None if the scores overflow; use
+    ///   align__global_max_qual to tell why.
+    pub fn align__global_max(&self, query: &Sequence, base_params: &AlnParams )
+                            -> Option<(/*path*/ Path, /*padded_ref*/ Sequence, /*padded_query*/ Sequence)> {
+        self.align__global_max_qual( query, None, base_params ).ok()
+    }
+
+    /// SeqGraph::align__global_max_qual -- as align__global_max, with match/mismatch scores
+    ///   scaled by the query's Phred base qualities (see align_matrix_qual), returning
+    ///   Err(AlnErr::QualLength) if they aren't one per query base
+    pub fn align__global_max_qual(&self, query: &Sequence, quals: Option<&[u8]>, base_params: &AlnParams )
+                                  -> Result<(/*path*/ Path, /*padded_ref*/ Sequence, /*padded_query*/ Sequence), AlnErr> {
+        self.align__global_max_cells::<AlnScore>( query, quals, base_params )
+    }
+
+    /// SeqGraph::align__global_max_cells -- as align__global_max_qual, with the DP score width
+    ///   chosen by S (eg. i64 for long references), returning Err(AlnErr::Overflow) rather
+    ///   than a corrupt alignment if the scores don't fit
+    pub fn align__global_max_cells<S: CellScore>(&self, query: &Sequence, quals: Option<&[u8]>, base_params: &AlnParams )
+                                   -> Result<(/*path*/ Path, /*padded_ref*/ Sequence, /*padded_query*/ Sequence), AlnErr> {
+        self._align_padded::<S>( query, quals, base_params, GraphAlnMode::Global ).map( |a| (a.0, a.1, a.2) )
+    }
+
+    /// SeqGraph::align__global_max_scored -- as align__global_max_cells, also giving the best
+    ///   path's score (including branch priors) and the runner-up's: the best score of an
+    ///   alignment taking another member at one of the branches the best one reaches, or
+    ///   None if it reaches none.  See sam::mapq.
+    pub fn align__global_max_scored<S: CellScore>(&self, query: &Sequence, quals: Option<&[u8]>, base_params: &AlnParams )
+                                    -> Result<(/*path*/ Path, /*padded_ref*/ Sequence, /*padded_query*/ Sequence,
+                                               /*score*/ i64, /*runner_up*/ Option<i64>), AlnErr> {
+        let (path, r, q, score, second) = try!( self._align_padded::<S>( query, quals, base_params, GraphAlnMode::Global ) );
+        Ok((path, r, q, score, if second == i64::min_value() { None } else { Some(second) }))
+    }
+
+
+    /// SeqGraph::align__top_k -- the best-scoring path for a local alignment of query, and up
+    ///   to k best non-overlapping local alignments against it, best first (see align_top_k)
+    pub fn align__top_k(&self, query: &Sequence, quals: Option<&[u8]>, base_params: &AlnParams, k: usize )
+                        -> Result<(/*path*/ Path, /*hits*/ Vec<(i64, Sequence, Sequence)>), AlnErr> {
+        let local = AlnParams::copy_but_mode( base_params, AlnMode::Local );
+        let (path, _, _, _, _) = try!( self._align_padded::<AlnScore>( query, quals, &local, GraphAlnMode::Global ) );
+        let hits = try!( align_top_k::<AlnScore>( &self.path_seq( &path ), query, quals, &local, k ) );
+        Ok((path, hits))
+    }
+
 
     /// SeqGraph::align__local_max -- align query to graph, testing each branch to a depth of 1
     ///   to quickly find a maximum
     pub fn align__local_max(&self, query: &Sequence, base_params: &AlnParams )
-                            -> Option<(/*path*/ Vec<u32>, /*padded_ref*/ Sequence, /*padded_query*/ Sequence)> {
+                            -> Option<(/*path*/ Path, /*padded_ref*/ Sequence, /*padded_query*/ Sequence)> {
+        self._align_padded::<AlnScore>( query, None, base_params, GraphAlnMode::LocalFollow ).ok().map( |a| (a.0, a.1, a.2) )
+    }
 
-        let mut _path = Vec::new();
-        let ref_len = self.max_len();
-        let mut m = Matrix::<Cell>::new( Cell(0), ref_len + 2, query.len() + 2 );
-        match SeqGraph::_align( &self.root, query,  &mut m, base_params, 0,
-                                 &mut _path, 0, GraphAlnMode::LocalFollow ) {
-            Some((score, path)) => {
-                let mut full_ref_v = Vec::new();
-                for s in GraphPath::from_graph( self, path.to_vec() ) {
-                    full_ref_v.extend( s.0 );
+    fn _edit_distance(node: &SeqNode, peq: &Peq, st: &mut BitState, rlocal: bool, path: &mut Path)
+                      -> (/*distance*/ i64, /*path*/ Path) {
+        match node {
+            &SeqNode::Nil => (st.distance( peq, rlocal ), path.clone()),
+            &SeqNode::Frag { id: ref id, val: ref val, next: ref next, ..} => {
+                path.nodes.push( id.clone() );
+                for &c in val.0.iter() {
+                    st.advance( peq, c );
                 }
-                let full_ref = Sequence(full_ref_v);
+                SeqGraph::_edit_distance( next, peq, st, rlocal, path )
+            },
+            &SeqNode::Repeat { id, unit: ref unit, min: lo, max: hi, next: ref next } => {
+                path.nodes.push( id );
+                let pos = path.nodes.len();
 
-                // FIXME - we can't use the existing alignment matrix, because we don't
-                //   know what path was tested last, and it's unlikely to be the maximum.
-                //   however, we can probably do better than this
-                let padded = align( &full_ref, query, base_params ).unwrap();
-                Some((path, padded.0, padded.1))
+                // try each copy count, continuing from the column after that many copies
+                let mut best = (i64::max_value(), Path::default());
+                for k in 0 .. hi + 1 {
+                    if k >= lo {
+                        path.truncate( pos );
+                        path.copies.insert( id, k );
+                        let mut tail_st = st.clone();
+                        let res = SeqGraph::_edit_distance( next, peq, &mut tail_st, rlocal, path );
+                        if res.0 < best.0 {
+                            best = res;
+                        }
+                    }
+                    if k < hi {
+                        for &c in unit.0.iter() {
+                            st.advance( peq, c );
+                        }
+                    }
+                }
+                best
             },
-            None => None
+            &SeqNode::Branch { id: ref id, members: ref members, ..} => {
+                path.nodes.push( id.clone() );
+                let pos = path.nodes.len();
+
+                let mut best = (i64::max_value(), Path::default());
+                for n in members {
+                    path.truncate( pos );
+                    // each member continues from the same column
+                    let mut member_st = st.clone();
+                    let res = SeqGraph::_edit_distance( n, peq, &mut member_st, rlocal, path );
+                    if res.0 < best.0 {
+                        best = res;
+                    }
+                }
+                best
+            }
+        }
+    }
+
+    /// SeqGraph::align__edit_distance -- unit-cost edit distance of query against the closest
+    ///   path through the graph, using Myers' bit-parallel algorithm.  Score-only: no padded
+    ///   alignment is produced.  Only params.mode is used, and only Global and SemiGlobal
+    ///   are supported; other modes return None.  Branch weights are ignored.
+    pub fn align__edit_distance(&self, query: &Sequence, base_params: &AlnParams )
+                                -> Option<(/*path*/ Path, /*distance*/ usize)> {
+        let ref_free = match base_params.mode {
+            AlnMode::Global => false,
+            AlnMode::SemiGlobal => true,
+            _ => return None
+        };
+        let peq = Peq::new( query );
+        let mut st = BitState::new( &peq, ref_free );
+        let mut _path = Path::default();
+        let (dist, path) = SeqGraph::_edit_distance( &self.root, &peq, &mut st, ref_free, &mut _path );
+        if dist == i64::max_value() {
+            None
+        } else {
+            Some((path, dist as usize))
         }
     }
 }
 
+impl<'a> Iterator for Haplotypes<'a> {
+    type Item = Haplotype;
+
+    fn next(&mut self) -> Option<Haplotype> {
+        while let Some((mut node, mut path)) = self.stack.pop() {
+            loop {
+                match node {
+                    &SeqNode::Nil => {
+                        let name = self.graph.path_name( &path, "|" );
+                        let seq = self.graph.path_seq( &path );
+                        return Some( Haplotype { path: path, name: name, seq: seq } );
+                    },
+                    &SeqNode::Frag { id, next: ref next, ..} => {
+                        path.nodes.push( id );
+                        node = next;
+                    },
+                    &SeqNode::Repeat { id, min: lo, max: hi, next: ref next, ..} => {
+                        for k in (lo .. hi + 1).rev() {
+                            let mut p = path.clone();
+                            p.nodes.push( id );
+                            p.copies.insert( id, k );
+                            self.stack.push( (next, p) );
+                        }
+                        break;
+                    },
+                    &SeqNode::Branch { id, members: ref members, ..} => {
+                        // an empty branch is a dead end, and yields no paths
+                        path.nodes.push( id );
+                        for n in members.iter().rev() {
+                            self.stack.push( (n, path.clone()) );
+                        }
+                        break;
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+impl Path {
+    // the copy count taken of repeat `id`, whose min is `lo`
+    fn count(&self, id: u32, lo: usize) -> usize {
+        self.copies.get( &id ).cloned().unwrap_or( lo )
+    }
+
+    // drop the nodes from `len` on, and the copy counts of any repeats among them
+    fn truncate(&mut self, len: usize) {
+        for id in self.nodes.drain( len .. ) {
+            self.copies.remove( &id );
+        }
+    }
+}
+
+impl From<Vec<u32>> for Path {
+    fn from(nodes: Vec<u32>) -> Path {
+        Path { nodes: nodes, copies: BTreeMap::new() }
+    }
+}
+
 impl<'a> GraphPath<'a> {
-    pub fn from_graph(graph: &'a SeqGraph, path: Vec<u32>) -> GraphPath {
+    pub fn from_graph(graph: &'a SeqGraph, path: &'a Path) -> GraphPath<'a> {
         GraphPath {
             curr_node: &graph.root,
             path: path,
@@ -297,23 +1286,34 @@ impl<'a> GraphPath<'a> {
         }
     }
 
-    fn _next(&mut self) -> Option<Sequence> {
-        if self.pos < self.path.len() {
-            assert_eq!( self.path[self.pos], self.curr_node.iden().unwrap() );
+    /// GraphPath::segments -- the (fragment id, sequence) pairs along the path, skipping
+    ///   branch nodes; a repeat gives its id and its unit repeated to the path's copy count
+    pub fn segments(mut self) -> Vec<(u32, Sequence)> {
+        let mut v = Vec::new();
+        while let Some(seg) = self._next() {
+            v.push(seg);
+        }
+        v
+    }
+
+    // the next fragment or repeat on the path
+    fn _next_node(&mut self) -> Option<&'a SeqNode> {
+        if self.pos < self.path.nodes.len() {
+            assert_eq!( self.path.nodes[self.pos], self.curr_node.iden().unwrap() );
 
-            match self.curr_node {
-                &SeqNode::Frag { val: ref val, next: ref next, ..} => {
+            let node = self.curr_node;
+            match node {
+                &SeqNode::Frag { next: ref next, ..} | &SeqNode::Repeat { next: ref next, ..} => {
                     self.pos += 1;
                     self.curr_node = next;
-                    Some(val.clone())
+                    Some(node)
                 },
                 &SeqNode::Branch { members: ref members, ..} => {
-                    assert_eq!( self.curr_node.iden(), Some(self.path[self.pos]) );
                     self.pos += 1;
                     for n in members {
-                        if n.iden() == Some(self.path[self.pos]) {
+                        if n.iden() == self.path.nodes.get( self.pos ).cloned() {
                             self.curr_node = n;
-                            return self._next()
+                            return self._next_node()
                         }
                     }
                     panic!("couldn't find thing");
@@ -324,14 +1324,33 @@ impl<'a> GraphPath<'a> {
             None
         }
     }
+
+    fn _next(&mut self) -> Option<(u32, Sequence)> {
+        let node = match self._next_node() {
+            Some(x) => x,
+            None => return None
+        };
+        Some( match node {
+            &SeqNode::Frag { id, val: ref val, ..} => (id, val.clone()),
+            &SeqNode::Repeat { id, unit: ref unit, min: lo, ..} => {
+                let copies = self.path.count( id, lo );
+                let mut v = Vec::with_capacity( unit.len() * copies );
+                for _ in 0 .. copies {
+                    v.extend( unit.0.iter().cloned() );
+                }
+                (id, Sequence(v))
+            },
+            _ => unreachable!()
+        })
+    }
 }
 
 impl<'a> Iterator for GraphPath<'a> {
     type Item = Sequence;
 
     fn next(&mut self) -> Option<Sequence> {
-        if self.pos < self.path.len() {
-            self._next()
+        if self.pos < self.path.nodes.len() {
+            self._next().map( |(_, val)| val )
         } else {
             None
         }
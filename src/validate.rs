@@ -0,0 +1,264 @@
+extern crate serde_json;
+
+use std::fmt;
+use std::collections::BTreeMap;
+use self::serde_json::Value as JSON_Val;
+use seq::*;
+use tree::*;
+
+
+/// Severity -- errors stop a reference from parsing or aligning as written; warnings are
+///   legal but probably unintended
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Warning,
+    Error
+}
+
+/// Issue -- one finding from SeqGraph::validate.  `location` is a JSON pointer (eg.
+///   `/1/branch/0`) to the offending element, empty for the document root.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Issue {
+    pub severity: Severity,
+    pub location: String,
+    pub message: String
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!( f, "{}\t{}\t{}",
+                match self.severity { Severity::Warning => "warning", Severity::Error => "error" },
+                if self.location.is_empty() { "/" } else { &self.location },
+                self.message )
+    }
+}
+
+const KNOWN_KEYS : [&'static str; 10] = ["id", "seq", "branch", "optional", "weights", "dist", "repeat", "min", "max", "capture"];
+
+// how far a distribution's probabilities may stray from summing to 1
+const DIST_TOLERANCE : f64 = 1e-6;
+
+struct Lint {
+    issues: Vec<Issue>,
+    // name -> location of its first use
+    names: BTreeMap<String, String>
+}
+
+impl Lint {
+    fn error(&mut self, loc: &str, msg: String) {
+        self.issues.push( Issue { severity: Severity::Error, location: loc.to_string(), message: msg } );
+    }
+
+    fn warn(&mut self, loc: &str, msg: String) {
+        self.issues.push( Issue { severity: Severity::Warning, location: loc.to_string(), message: msg } );
+    }
+
+    fn element(&mut self, elem: &JSON_Val, loc: &str) {
+        match elem {
+            &JSON_Val::String(ref s) => self.seq( s, loc ),
+            &JSON_Val::Array(ref l) => {
+                if l.is_empty() {
+                    self.warn( loc, "empty list".to_string() );
+                }
+                for (i, x) in l.iter().enumerate() {
+                    self.element( x, &format!("{}/{}", loc, i) );
+                }
+            },
+            &JSON_Val::Object(ref map) => self.object( map, loc ),
+            _ => self.error( loc, "expected a string, list or object".to_string() )
+        }
+    }
+
+    fn seq(&mut self, s: &str, loc: &str) {
+        match Sequence::from_str( s ) {
+            Ok(ref seq) if seq.len() == 0 => self.warn( loc, "zero-length fragment".to_string() ),
+            Ok(_) => (),
+            Err(msg) => self.error( loc, msg )
+        }
+    }
+
+    fn object(&mut self, map: &BTreeMap<String, JSON_Val>, loc: &str) {
+        match map.get("id") {
+            Some(&JSON_Val::String(ref name)) => {
+                if let Some(first) = self.names.get(name).cloned() {
+                    self.warn( loc, format!("duplicate name '{}' (first used at {})",
+                                            name, if first.is_empty() { "/" } else { &first }) );
+                } else {
+                    self.names.insert( name.clone(), loc.to_string() );
+                }
+            },
+            Some(_) => self.error( loc, "\"id\" must be a string".to_string() ),
+            None => ()
+        }
+
+        for key in map.keys() {
+            if !KNOWN_KEYS.contains( &key.as_str() ) {
+                self.warn( loc, format!("unknown key '{}' is ignored", key) );
+            }
+        }
+
+        let kinds : Vec<&str> = ["seq", "branch", "optional", "dist", "repeat"].iter().cloned().filter( |k| map.contains_key(*k) ).collect();
+        if kinds.len() > 1 {
+            self.error( loc, format!("ambiguous node: has each of {}", kinds.join(", ")) );
+        } else if kinds.is_empty() {
+            self.error( loc, "node has none of \"seq\", \"branch\", \"optional\", \"dist\" or \"repeat\"".to_string() );
+        }
+        if !map.contains_key("repeat") {
+            for key in ["min", "max"].iter().filter( |k| map.contains_key(**k) ) {
+                self.warn( loc, format!("\"{}\" only applies to repeat nodes, and is ignored", key) );
+            }
+        }
+
+        match map.get("seq") {
+            Some(&JSON_Val::String(ref s)) => self.seq( s, &format!("{}/seq", loc) ),
+            Some(_) => self.error( &format!("{}/seq", loc), "\"seq\" must be a string".to_string() ),
+            None => ()
+        }
+        match (map.get("capture"), map.get("seq")) {
+            (Some(&JSON_Val::Bool(_)), Some(_)) | (None, _) => (),
+            (Some(&JSON_Val::Bool(_)), None) => self.warn( loc, "\"capture\" only applies to \"seq\" nodes, and is ignored".to_string() ),
+            (Some(_), _) => self.error( &format!("{}/capture", loc), "\"capture\" must be true or false".to_string() )
+        }
+        match map.get("branch") {
+            Some(&JSON_Val::Array(ref l)) => self.branch( l, &format!("{}/branch", loc) ),
+            Some(_) => self.error( &format!("{}/branch", loc), "\"branch\" must be a list".to_string() ),
+            None => ()
+        }
+        match (map.get("weights"), map.get("branch"), map.get("optional")) {
+            (Some(w), Some(&JSON_Val::Array(ref l)), _) => self.weights( w, l.len(), &format!("{}/weights", loc) ),
+            (Some(w), None, Some(_)) => self.weights( w, 2, &format!("{}/weights", loc) ),
+            (Some(_), None, None) => self.warn( loc, "\"weights\" only applies to branch and optional nodes, and is ignored".to_string() ),
+            _ => ()
+        }
+        match map.get("optional") {
+            Some(x) => self.element( x, &format!("{}/optional", loc) ),
+            None => ()
+        }
+        match map.get("repeat") {
+            Some(&JSON_Val::String(ref s)) => self.repeat( s, map, &format!("{}/repeat", loc) ),
+            Some(_) => self.error( &format!("{}/repeat", loc), "\"repeat\" must be a string".to_string() ),
+            None => ()
+        }
+        match map.get("dist") {
+            Some(d) => self.dist( d, &format!("{}/dist", loc) ),
+            None => ()
+        }
+    }
+
+    fn repeat(&mut self, unit: &str, map: &BTreeMap<String, JSON_Val>, loc: &str) {
+        match Sequence::from_str( unit ) {
+            Ok(ref seq) if seq.len() == 0 => self.warn( loc, "zero-length repeat unit".to_string() ),
+            Ok(_) => (),
+            Err(msg) => self.error( loc, msg )
+        }
+        let count = |key: &str| map.get(key).and_then( |v| v.as_u64() );
+        match (count("min"), count("max")) {
+            (Some(lo), Some(hi)) => if lo > hi {
+                self.error( loc, format!("min copies ({}) is greater than max ({})", lo, hi) );
+            },
+            _ => self.error( loc, "a repeat needs whole-number \"min\" and \"max\" copy counts".to_string() )
+        }
+    }
+
+    fn branch(&mut self, l: &[JSON_Val], loc: &str) {
+        if l.is_empty() {
+            self.error( loc, "empty branch: no path can pass through it".to_string() );
+        }
+        let content : Vec<JSON_Val> = l.iter().map( unnamed ).collect();
+        for (i, x) in l.iter().enumerate() {
+            let member_loc = format!("{}/{}", loc, i);
+            if let Some(j) = (0 .. i).find( |&j| content[j] == content[i] ) {
+                self.warn( &member_loc, format!("identical to branch member {}", j) );
+            }
+            match x {
+                // an empty member is a deletion allele, so no zero-length warning
+                &JSON_Val::String(ref s) if s.is_empty() => (),
+                _ => self.element( x, &member_loc )
+            }
+        }
+    }
+
+    fn weights(&mut self, w: &JSON_Val, n_members: usize, loc: &str) {
+        match w {
+            &JSON_Val::Array(ref l) => {
+                if l.len() != n_members {
+                    self.error( loc, format!("{} weights for {} members", l.len(), n_members) );
+                }
+                for (i, x) in l.iter().enumerate() {
+                    match x.as_f64() {
+                        Some(p) if p > 0.0 => (),
+                        _ => self.error( &format!("{}/{}", loc, i), "a weight must be a positive number".to_string() )
+                    }
+                }
+            },
+            _ => self.error( loc, "\"weights\" must be a list of numbers".to_string() )
+        }
+    }
+
+    fn dist(&mut self, d: &JSON_Val, loc: &str) {
+        self.error( loc, "\"dist\" nodes are not yet supported".to_string() );
+
+        let positions = match d {
+            &JSON_Val::Array(ref l) => l,
+            _ => return self.error( loc, "\"dist\" must be a list of {base: probability} objects".to_string() )
+        };
+        for (i, pos) in positions.iter().enumerate() {
+            let pos_loc = format!("{}/{}", loc, i);
+            let probs = match pos {
+                &JSON_Val::Object(ref m) => m,
+                _ => { self.error( &pos_loc, "expected a {base: probability} object".to_string() ); continue }
+            };
+            let mut total = 0.0;
+            for (base, p) in probs {
+                match Sequence::from_str( base ) {
+                    Ok(ref s) if s.len() == 1 && s.0[0] != HYPHEN => (),
+                    _ => self.error( &pos_loc, format!("'{}' is not a base", base) )
+                }
+                match p.as_f64() {
+                    Some(x) if x >= 0.0 => total += x,
+                    _ => self.error( &pos_loc, format!("probability of '{}' must be a non-negative number", base) )
+                }
+            }
+            if (total - 1.0).abs() > DIST_TOLERANCE {
+                self.error( &pos_loc, format!("probabilities sum to {}, not 1", total) );
+            }
+        }
+    }
+}
+
+// an element's content, ignoring names and whether fragments are written as strings or
+//   {"seq": ...} objects, for comparing branch members
+fn unnamed(elem: &JSON_Val) -> JSON_Val {
+    match elem {
+        &JSON_Val::String(ref s) => {
+            let mut m = BTreeMap::new();
+            m.insert( "seq".to_string(), JSON_Val::String( s.to_uppercase() ) );
+            JSON_Val::Object(m)
+        },
+        &JSON_Val::Array(ref l) => JSON_Val::Array( l.iter().map( unnamed ).collect() ),
+        &JSON_Val::Object(ref map) => JSON_Val::Object(
+            map.iter()
+                .filter( |&(k, _)| k != "id" )
+                .map( |(k, v)| (k.clone(), match (k.as_str(), v) {
+                    ("seq", &JSON_Val::String(ref s)) => JSON_Val::String( s.to_uppercase() ),
+                    _ => unnamed(v)
+                }) )
+                .collect() ),
+        _ => elem.clone()
+    }
+}
+
+
+impl SeqGraph {
+    /// SeqGraph::validate -- check a graph-JSON reference, returning every problem found
+    ///   rather than stopping at the first.  This works on the JSON text, so it also reports
+    ///   what from_json would reject or silently ignore (eg. unknown keys).
+    pub fn validate( serialized: &str ) -> Vec<Issue> {
+        let mut lint = Lint { issues: Vec::new(), names: BTreeMap::new() };
+        match serde_json::from_str::<JSON_Val>( serialized ) {
+            Ok(value) => lint.element( &value, "" ),
+            Err(e) => lint.error( "", format!("invalid JSON: {}", e) )
+        }
+        lint.issues
+    }
+}